@@ -0,0 +1,220 @@
+use std::f64::consts::LN_2;
+use std::io::{Read, Write, Result as IoResult, Error as IoError, ErrorKind as IoErrorKind};
+
+use key::Key;
+
+const MAGIC: &[u8; 4] = b"CHBF";
+
+// Limits for headers of read filters, so a corrupted file can't cause a panic or a huge allocation.
+// 2^36 bits are 8 GiB, a filter for billions of keys.
+const MAX_BIT_COUNT: u64 = 1 << 36;
+const MAX_HASH_COUNT: u64 = 64;
+
+// Two independent FNV-1a hashes are combined to get `k` ones (Kirsch and Mitzenmacher).
+// `DefaultHasher` isn't used, because its algorithm can change and the filter is persistent.
+const FNV_PRIME: u64 = 0x100000001b3;
+const FNV_OFFSETS: [u64; 2] = [0xcbf29ce484222325, 0x84222325cbf29ce4];
+
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    bit_count: u64,
+    hash_count: u32,
+    len: usize
+}
+
+impl BloomFilter {
+    pub fn new(capacity: usize, fp_rate: f64) -> BloomFilter {
+        assert!(0. < fp_rate && fp_rate < 1.);
+
+        let capacity = capacity.max(1) as f64;
+        let bit_count = (-capacity * fp_rate.ln() / (LN_2 * LN_2)).ceil().max(64.) as u64;
+        let hash_count = (bit_count as f64 / capacity * LN_2).round().max(1.) as u32;
+
+        BloomFilter {
+            bits: vec![0; ((bit_count + 63) / 64) as usize],
+            bit_count,
+            hash_count,
+            len: 0
+        }
+    }
+
+    // The number of inserted keys.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn contains(&self, key: &Key) -> bool {
+        self.positions(key).all(|pos| self.bits[(pos / 64) as usize] & (1 << (pos % 64)) != 0)
+    }
+
+    // Returns `false` if the key is (probably) already present.
+    pub fn insert(&mut self, key: &Key) -> bool {
+        let mut inserted = false;
+
+        for pos in self.positions(key).collect::<Vec<_>>() {
+            let word = &mut self.bits[(pos / 64) as usize];
+            let mask = 1 << (pos % 64);
+
+            inserted |= *word & mask == 0;
+            *word |= mask;
+        }
+
+        if inserted {
+            self.len += 1;
+        }
+
+        inserted
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> IoResult<()> {
+        writer.write_all(MAGIC)?;
+        write_u64(writer, self.bit_count)?;
+        write_u64(writer, self.hash_count as u64)?;
+        write_u64(writer, self.len as u64)?;
+
+        for &word in &self.bits {
+            write_u64(writer, word)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> IoResult<BloomFilter> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+
+        if &magic != MAGIC {
+            return Err(IoError::new(IoErrorKind::InvalidData, "Not a bloom filter"));
+        }
+
+        let bit_count = read_u64(reader)?;
+        let hash_count = read_u64(reader)?;
+        let len = read_u64(reader)? as usize;
+
+        if bit_count == 0 || bit_count > MAX_BIT_COUNT || hash_count == 0 || hash_count > MAX_HASH_COUNT {
+            return Err(IoError::new(IoErrorKind::InvalidData, "Invalid bloom filter header"));
+        }
+
+        let word_count = bit_count.checked_add(63)
+            .map(|bits| bits / 64)
+            .ok_or_else(|| IoError::new(IoErrorKind::InvalidData, "Invalid bloom filter header"))?;
+
+        // Words are pushed as they are read, so a truncated file fails before allocating the whole filter.
+        let mut bits = Vec::new();
+
+        for _ in 0..word_count {
+            bits.push(read_u64(reader)?);
+        }
+
+        Ok(BloomFilter { bits, bit_count, hash_count: hash_count as u32, len })
+    }
+
+    fn positions<'a>(&'a self, key: &Key) -> impl Iterator<Item=u64> + 'a {
+        let bytes = key.as_ref().as_bytes();
+        let (h1, h2) = (fnv1a(FNV_OFFSETS[0], bytes), fnv1a(FNV_OFFSETS[1], bytes) | 1);
+
+        (0..self.hash_count as u64)
+            .map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % self.bit_count)
+    }
+}
+
+// A stable hash for keys kept outside of filters.
+pub fn hash(bytes: &[u8]) -> u64 {
+    fnv1a(FNV_OFFSETS[0], bytes)
+}
+
+fn fnv1a(offset: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(offset, |hash, &byte| (hash ^ byte as u64).wrapping_mul(FNV_PRIME))
+}
+
+fn write_u64<W: Write>(writer: &mut W, value: u64) -> IoResult<()> {
+    let mut buffer = [0; 8];
+
+    for (i, byte) in buffer.iter_mut().enumerate() {
+        *byte = (value >> (8 * i)) as u8;
+    }
+
+    writer.write_all(&buffer)
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> IoResult<u64> {
+    let mut buffer = [0; 8];
+    reader.read_exact(&mut buffer)?;
+
+    Ok(buffer.iter().enumerate().fold(0, |value, (i, &byte)| value | (byte as u64) << (8 * i)))
+}
+
+#[cfg(test)]
+fn key(idx: usize) -> Key {
+    Key::from(format!("example.com/{}", idx))
+}
+
+#[test]
+fn it_remembers_inserted() {
+    let mut filter = BloomFilter::new(1000, 0.01);
+
+    for idx in 0..1000 {
+        filter.insert(&key(idx));
+    }
+
+    assert!((0..1000).all(|idx| filter.contains(&key(idx))));
+    assert!(!filter.insert(&key(42)));
+    assert!(filter.len() <= 1000);
+}
+
+#[test]
+fn it_keeps_fp_rate() {
+    let mut filter = BloomFilter::new(1000, 0.01);
+
+    for idx in 0..1000 {
+        filter.insert(&key(idx));
+    }
+
+    let false_positives = (1000..11000).filter(|&idx| filter.contains(&key(idx))).count();
+
+    assert!(false_positives < 200, "{} false positives", false_positives);
+}
+
+#[test]
+fn it_persists() {
+    let mut filter = BloomFilter::new(100, 0.001);
+
+    for idx in 0..100 {
+        filter.insert(&key(idx));
+    }
+
+    let mut buffer = Vec::new();
+    filter.write_to(&mut buffer).unwrap();
+
+    let restored = BloomFilter::read_from(&mut &buffer[..]).unwrap();
+
+    assert_eq!(restored.len(), filter.len());
+    assert_eq!(restored.bits, filter.bits);
+    assert!((0..100).all(|idx| restored.contains(&key(idx))));
+    assert!(BloomFilter::read_from(&mut &b"JUNK"[..]).is_err());
+}
+
+#[test]
+fn it_rejects_corrupted_headers() {
+    let header = |bit_count: u64, hash_count: u64| {
+        let mut buffer = MAGIC.to_vec();
+
+        for &value in &[bit_count, hash_count, 0] {
+            write_u64(&mut buffer, value).unwrap();
+        }
+
+        buffer
+    };
+
+    for &(bit_count, hash_count) in &[(0, 7), (64, 0), (u64::max_value(), 7), (MAX_BIT_COUNT + 1, 7), (64, 1000)] {
+        let error = BloomFilter::read_from(&mut &header(bit_count, hash_count)[..]).err().unwrap();
+        assert_eq!(error.kind(), IoErrorKind::InvalidData);
+    }
+
+    // The header is valid, but the body is truncated.
+    assert!(BloomFilter::read_from(&mut &header(MAX_BIT_COUNT, 7)[..]).is_err());
+}
@@ -0,0 +1,366 @@
+use std::env;
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Error as IoError, ErrorKind as IoErrorKind};
+
+use toml;
+use toml::value::{Table, Value};
+
+const CONFIG_ENV: &str = "CHOKUREI_CONFIG";
+const DEFAULT_PATH: &str = "chokurei.toml";
+
+// `CHOKUREI_RAIDER_MIN_INTERVAL=7200` overrides `min_interval` in the `[raider]` section.
+const ENV_PREFIX: &str = "CHOKUREI_";
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub kafka: KafkaConfig,
+    pub topics: TopicsConfig,
+    pub scout: ScoutConfig,
+    pub raider: RaiderConfig,
+    pub compounder: CompounderConfig,
+    pub rater: RaterConfig
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct KafkaConfig {
+    pub url: String
+}
+
+impl Default for KafkaConfig {
+    fn default() -> KafkaConfig {
+        KafkaConfig {
+            url: "127.0.0.1:9092".to_owned()
+        }
+    }
+}
+
+impl KafkaConfig {
+    pub fn hosts(&self) -> Vec<String> {
+        vec![self.url.clone()]
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TopicsConfig {
+    pub feeds: String,
+    pub entries: String,
+    pub expert: String,
+    pub fakes: String,
+    pub ratings: String
+}
+
+impl Default for TopicsConfig {
+    fn default() -> TopicsConfig {
+        TopicsConfig {
+            feeds: "feeds".to_owned(),
+            entries: "entries".to_owned(),
+            expert: "expert".to_owned(),
+            fakes: "fakes".to_owned(),
+            ratings: "ratings".to_owned()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ScoutConfig {
+    pub adding_url: String
+}
+
+impl Default for ScoutConfig {
+    fn default() -> ScoutConfig {
+        ScoutConfig {
+            adding_url: "127.0.0.1:3042".to_owned()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RaiderConfig {
+    pub min_interval: u32,
+    pub max_interval: u32,
+    pub promptness: f32,
+    pub default_utc_offset: i32,
+    pub max_seen_items: usize,
+    pub min_full_text_length: usize,
+    pub retry_interval: u32,
+    pub retry_file: String,
+    pub max_document_retries: u32,
+    pub dedup_file: String,
+    pub dedup_capacity: usize,
+    pub dedup_fp_rate: f64,
+    pub dedup_save_interval: i64,
+    pub robots_ttl: u64,
+    pub max_crawl_delay: u64,
+    pub max_connections: usize,
+    pub max_host_connections: usize,
+    pub dns_cache_ttl: u64,
+    pub connect_timeout: u64,
+    pub request_timeout: u64,
+    pub max_feed_size: usize,
+    pub max_document_size: usize,
+    pub host_rate: f64,
+    pub host_burst: u32,
+    pub host_failure_threshold: u32,
+    pub host_cooldown: u64,
+    pub feed_concurrency: usize,
+    pub download_concurrency: usize,
+    pub extraction_threads: usize,
+    pub extraction_queue: usize,
+    pub slow_extraction: u64,
+    pub extraction_report_interval: i64
+}
+
+impl Default for RaiderConfig {
+    fn default() -> RaiderConfig {
+        RaiderConfig {
+            min_interval: 3600,
+            max_interval: 24 * 3600,
+            promptness: 0.5,
+            default_utc_offset: 0,
+            max_seen_items: 500,
+            min_full_text_length: 1000,
+            retry_interval: 600,
+            retry_file: "retries.json".to_owned(),
+            max_document_retries: 5,
+            dedup_file: "dedup.bloom".to_owned(),
+            dedup_capacity: 1_000_000,
+            dedup_fp_rate: 0.001,
+            dedup_save_interval: 60,
+            robots_ttl: 24 * 3600,
+            max_crawl_delay: 60,
+            max_connections: 32,
+            max_host_connections: 4,
+            dns_cache_ttl: 300,
+            connect_timeout: 10,
+            request_timeout: 60,
+            max_feed_size: 5 * 1024 * 1024,
+            max_document_size: 2 * 1024 * 1024,
+            host_rate: 1.,
+            host_burst: 5,
+            host_failure_threshold: 5,
+            host_cooldown: 600,
+            feed_concurrency: 4,
+            download_concurrency: 16,
+            extraction_threads: 4,
+            extraction_queue: 8,
+            slow_extraction: 1000,
+            extraction_report_interval: 3600
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CompounderConfig {
+    pub group: String,
+    pub dictionary_file: String,
+    pub similarity_threshold: f32,
+    pub snapshot_file: String,
+    pub snapshot_interval: u64
+}
+
+impl Default for CompounderConfig {
+    fn default() -> CompounderConfig {
+        CompounderConfig {
+            group: "compounder".to_owned(),
+            dictionary_file: "dictionary.txt".to_owned(),
+            similarity_threshold: 0.7,
+            snapshot_file: "snapshot.json".to_owned(),
+            snapshot_interval: 300
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RaterConfig {
+    pub windows: Vec<u32>,
+    pub expiration_period: i64
+}
+
+impl Default for RaterConfig {
+    fn default() -> RaterConfig {
+        RaterConfig {
+            // The "fakes" topic keeps two weeks of entries, so longer windows make no sense.
+            windows: vec![24 * 3600, 7 * 24 * 3600, 14 * 24 * 3600],
+            expiration_period: 600
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(IoError),
+    Parse(toml::de::Error)
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            ConfigError::Io(ref error) => write!(f, "Cannot read config: {}", error),
+            ConfigError::Parse(ref error) => write!(f, "Invalid config: {}", error)
+        }
+    }
+}
+
+impl From<IoError> for ConfigError {
+    fn from(error: IoError) -> ConfigError {
+        ConfigError::Io(error)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(error: toml::de::Error) -> ConfigError {
+        ConfigError::Parse(error)
+    }
+}
+
+pub fn load() -> Result<Config, ConfigError> {
+    let path = env::var(CONFIG_ENV).ok();
+
+    let data = match File::open(path.as_ref().map_or(DEFAULT_PATH, String::as_str)) {
+        Ok(mut file) => {
+            let mut data = String::new();
+            file.read_to_string(&mut data)?;
+            data
+        },
+        // The default file is optional, but the explicitly specified one isn't.
+        Err(ref error) if error.kind() == IoErrorKind::NotFound && path.is_none() => String::new(),
+        Err(error) => return Err(error.into())
+    };
+
+    parse(&data, env::vars())
+}
+
+fn parse<I: Iterator<Item=(String, String)>>(data: &str, vars: I) -> Result<Config, ConfigError> {
+    let mut root = toml::from_str::<Table>(data)?;
+    let file = root.clone();
+
+    for (name, value) in vars {
+        if !name.starts_with(ENV_PREFIX) {
+            continue;
+        }
+
+        let path = name[ENV_PREFIX.len()..].to_lowercase();
+        let mut parts = path.splitn(2, '_');
+
+        let (section, key) = match (parts.next(), parts.next()) {
+            (Some(section), Some(key)) => (section, key),
+            _ => continue
+        };
+
+        let parsed = parse_env_value(&value);
+
+        // String settings can look like numbers, e.g. a topic "123", so the type of the field decides.
+        let value = if parsed.is_str() || fits(&file, section, key, parsed.clone()) {
+            parsed
+        } else {
+            Value::String(value)
+        };
+
+        set(&mut root, section, key, value);
+    }
+
+    Ok(Value::Table(root).try_into()?)
+}
+
+fn set(root: &mut Table, section: &str, key: &str, value: Value) {
+    let section = root.entry(section.to_owned()).or_insert_with(|| Value::Table(Table::new()));
+
+    if let Value::Table(ref mut table) = *section {
+        table.insert(key.to_owned(), value);
+    }
+}
+
+// Whether the config accepts the value, other overrides aren't taken into account.
+fn fits(root: &Table, section: &str, key: &str, value: Value) -> bool {
+    let mut root = root.clone();
+    set(&mut root, section, key, value);
+
+    Value::Table(root).try_into::<Config>().is_ok()
+}
+
+// Everything that isn't a valid TOML value is considered as a bare string.
+fn parse_env_value(value: &str) -> Value {
+    toml::from_str::<Table>(&format!("value = {}", value))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(value.to_owned()))
+}
+
+#[cfg(test)]
+fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    pairs.iter().map(|&(k, v)| (k.to_owned(), v.to_owned())).collect()
+}
+
+#[test]
+fn it_uses_defaults() {
+    let config = parse("", vars(&[]).into_iter()).unwrap();
+
+    assert_eq!(config.kafka.url, "127.0.0.1:9092");
+    assert_eq!(config.topics.feeds, "feeds");
+    assert_eq!(config.raider.min_interval, 3600);
+    assert_eq!(config.compounder.similarity_threshold, 0.7);
+}
+
+#[test]
+fn it_reads_file() {
+    let data = r#"
+        [kafka]
+        url = "kafka.local:9092"
+
+        [raider]
+        promptness = 0.25
+    "#;
+
+    let config = parse(data, vars(&[]).into_iter()).unwrap();
+
+    assert_eq!(config.kafka.url, "kafka.local:9092");
+    assert_eq!(config.raider.promptness, 0.25);
+    assert_eq!(config.raider.max_interval, 24 * 3600);
+}
+
+#[test]
+fn it_overrides_by_env() {
+    let data = r#"
+        [raider]
+        min_interval = 100
+    "#;
+
+    let config = parse(data, vars(&[
+        ("CHOKUREI_RAIDER_MIN_INTERVAL", "7200"),
+        ("CHOKUREI_KAFKA_URL", "localhost:9092"),
+        ("CHOKUREI_RATER_WINDOWS", "[60, 3600]"),
+        ("CHOKUREI_CONFIG", "other.toml"),
+        ("RAIDER_MAX_INTERVAL", "1")
+    ]).into_iter()).unwrap();
+
+    assert_eq!(config.raider.min_interval, 7200);
+    assert_eq!(config.raider.max_interval, 24 * 3600);
+    assert_eq!(config.kafka.url, "localhost:9092");
+    assert_eq!(config.rater.windows, [60, 3600]);
+}
+
+#[test]
+fn it_keeps_numeric_strings() {
+    let config = parse("", vars(&[
+        ("CHOKUREI_TOPICS_FEEDS", "123"),
+        ("CHOKUREI_COMPOUNDER_GROUP", "true"),
+        ("CHOKUREI_RAIDER_MIN_INTERVAL", "7200")
+    ]).into_iter()).unwrap();
+
+    assert_eq!(config.topics.feeds, "123");
+    assert_eq!(config.compounder.group, "true");
+    assert_eq!(config.raider.min_interval, 7200);
+}
+
+#[test]
+fn it_rejects_invalid_types() {
+    assert!(parse("", vars(&[("CHOKUREI_RAIDER_MIN_INTERVAL", "soon")]).into_iter()).is_err());
+}
@@ -5,7 +5,10 @@ extern crate url;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde;
+extern crate toml;
 
 pub mod logger;
 pub mod key;
 pub mod messages;
+pub mod config;
+pub mod bloom;
@@ -9,10 +9,63 @@ pub struct Feed {
     pub url: Url,
     pub interval: u32,
     #[serde(with = "timespec_fmt")]
-    pub augmented: Timespec
+    pub augmented: Timespec,
+    // The number of items in the last fetched version of the feed.
+    #[serde(default)]
+    pub size: u32,
+    // How many of the last new items carry the whole article, their pages aren't fetched.
+    #[serde(default)]
+    pub full_text: u32,
+    #[serde(default)]
+    pub etag: Option<String>,
+    #[serde(default)]
+    pub last_modified: Option<String>,
+    // Publisher hints: the minimal interval in seconds, GMT hours (0-23) and weekdays
+    // (0 is Sunday) when the feed isn't updated.
+    #[serde(default)]
+    pub ttl: Option<u32>,
+    #[serde(default)]
+    pub skip_hours: Vec<u8>,
+    #[serde(default)]
+    pub skip_days: Vec<u8>,
+    // The number of failed visits in a row and the delay of the next visit, which overrides `interval`.
+    #[serde(default)]
+    pub failures: u32,
+    #[serde(default)]
+    pub retry: Option<u32>,
+    // The offset in seconds east of UTC for dates without zones, learned from dates with zones.
+    #[serde(default)]
+    pub timezone: Option<i32>,
+    // Hashes (`bloom::hash`) of ids of recently seen items, the current version of the feed goes first.
+    // Ids can be long, hashes keep the message small.
+    #[serde(default)]
+    pub seen: Vec<u64>
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl Feed {
+    // A feed that has never been visited.
+    pub fn new(source: Url, url: Url) -> Feed {
+        Feed {
+            source,
+            url,
+            interval: 0,
+            augmented: Timespec::new(0, 0),
+            size: 0,
+            full_text: 0,
+            etag: None,
+            last_modified: None,
+            ttl: None,
+            skip_hours: Vec::new(),
+            skip_days: Vec::new(),
+            failures: 0,
+            retry: None,
+            timezone: None,
+            seen: Vec::new()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Entry {
     #[serde(with = "url_fmt")]
     pub source: Url,
@@ -21,10 +74,84 @@ pub struct Entry {
     pub title: String,
     pub author: Option<String>,
     pub content: String,
+    // Where `content` is taken from.
+    #[serde(default)]
+    pub content_source: ContentSource,
+    // The structure of `content`: paragraphs, headings, quotes and list items in order.
+    #[serde(default)]
+    pub blocks: Vec<Block>,
     #[serde(with = "timespec_fmt")]
     pub published: Timespec
 }
 
+// A part of the article. Links inside the block are kept with their texts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Block {
+    pub kind: BlockKind,
+    pub text: String,
+    #[serde(default)]
+    pub links: Vec<Link>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockKind {
+    Paragraph,
+    Heading,
+    Quote,
+    ListItem
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Link {
+    #[serde(with = "url_fmt")]
+    pub url: Url,
+    pub text: String
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentSource {
+    Feed,
+    Page
+}
+
+impl Default for ContentSource {
+    fn default() -> ContentSource {
+        ContentSource::Feed
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Verdict {
+    #[serde(with = "url_fmt")]
+    pub url: Url,
+    pub score: f32
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Fake {
+    pub entry: Entry,
+    pub score: f32,
+    // An offset of the verdict on the "expert" topic.
+    pub verdict: i64
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Rating {
+    #[serde(with = "url_fmt")]
+    pub source: Url,
+    pub penalties: Vec<Penalty>,
+    #[serde(with = "timespec_fmt")]
+    pub updated: Timespec
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Penalty {
+    pub window: u32,
+    pub points: f32
+}
+
 mod timespec_fmt {
     use time::Timespec;
     use serde::{Serializer, Deserializer, Deserialize};
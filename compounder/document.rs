@@ -7,9 +7,6 @@ use rust_stemmers::{Algorithm, Stemmer};
 
 use common::messages::Entry;
 
-// TODO: pass via environment or config.
-const DICTIONARY_FILE: &str = "dictionary.txt";
-
 lazy_static! {
     static ref STOP_WORDS: HashSet<&'static str> = {
         include_str!("stopwords.txt")
@@ -21,7 +18,7 @@ lazy_static! {
     static ref EN_STEMMER: Stemmer = Stemmer::create(Algorithm::English);
 
     static ref DICTIONARY_DATA: String = {
-        let mut file = File::open(DICTIONARY_FILE).unwrap();
+        let mut file = File::open(&::CONFIG.compounder.dictionary_file).unwrap();
 
         let mut data = String::new();
         file.read_to_string(&mut data).unwrap();
@@ -37,11 +34,13 @@ lazy_static! {
     };
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct Document {
     entry: Entry,
     vector: Vector,
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct Vector(Vec<(u32, f32)>);
 
 impl Document {
@@ -3,6 +3,9 @@ extern crate common;
 extern crate log;
 #[macro_use]
 extern crate lazy_static;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
 extern crate futures;
 extern crate tokio_core;
 extern crate time;
@@ -14,29 +17,40 @@ extern crate rust_stemmers;
 use std::sync::Mutex;
 use std::collections::HashMap;
 use std::thread;
+use std::time::Duration;
 
-use kafka::consumer::{Consumer, FetchOffset};
+use kafka::consumer::{Consumer, FetchOffset, GroupOffsetStorage};
 use kafka::producer::{Producer, Record};
 use url::Url;
 
 use common::logger;
-use common::messages::Entry;
+use common::config::{self, Config};
+use common::messages::{Entry, Fake, Verdict};
 use document::Document;
 
 mod document;
+mod snapshot;
 
-const KAFKA_URL: &str = "127.0.0.1:9092";
+#[derive(Serialize, Deserialize)]
+struct Mark {
+    id: i64,
+    score: f32,
+    document: usize,
+}
 
+#[derive(Serialize, Deserialize)]
 struct Cluster {
     documents: Vec<Document>,
-    fake: bool,
+    scores: Vec<f32>,
+    marks: Vec<Mark>,
 }
 
 impl Cluster {
     pub fn new() -> Cluster {
         Cluster {
             documents: Vec::new(),
-            fake: false,
+            scores: Vec::new(),
+            marks: Vec::new(),
         }
     }
 
@@ -52,51 +66,92 @@ impl Cluster {
             .sum::<f32>() / count as f32
     }
 
-    pub fn add_document(&mut self, document: Document) {
-        if self.fake {
-            report_about_fake(&document);
-        }
+    pub fn add_document(&mut self, fakes: &mut Vec<Fake>, document: Document) {
+        // s_y = s_x * sim(x, y), where x is the document flagged by the expert.
+        let best = self.marks.iter()
+            .map(|mark| {
+                let flagged = &self.documents[mark.document];
+                (mark.id, mark.score * flagged.distance(&document))
+            })
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+
+        let score = match best {
+            Some((id, score)) => {
+                report_about_fake(fakes, &document, id, score);
+                score
+            },
+            None => 0.
+        };
 
         self.documents.push(document);
+        self.scores.push(score);
     }
 
-    pub fn mark_as_fake(&mut self) {
-        if self.fake {
-            return;
-        }
-
-        for document in &self.documents {
-            report_about_fake(&document);
+    pub fn mark_as_fake(&mut self, fakes: &mut Vec<Fake>, url: &Url, id: i64, score: f32) {
+        let flagged = match self.documents.iter().position(|document| &document.entry().url == url) {
+            Some(flagged) => flagged,
+            None => return
+        };
+
+        self.marks.push(Mark { id, score, document: flagged });
+
+        for idx in 0..self.documents.len() {
+            let propagated = if idx == flagged {
+                score
+            } else {
+                score * self.documents[flagged].distance(&self.documents[idx])
+            };
+
+            // Report only if the document becomes more suspicious than it was.
+            if propagated > self.scores[idx] {
+                self.scores[idx] = propagated;
+                report_about_fake(fakes, &self.documents[idx], id, propagated);
+            }
         }
     }
 }
 
+// Offsets of the next messages to process. Both topics have a single partition.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct Offsets {
+    entries: i64,
+    expert: i64,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct State {
+    clusters: Vec<Cluster>,
+    offsets: Offsets,
+}
+
 lazy_static! {
-    static ref CLUSTERS: Mutex<Vec<Cluster>> = Mutex::new(Vec::new());
+    static ref CONFIG: Config = config::load().unwrap();
+
+    static ref STATE: Mutex<State> = Mutex::new(State::default());
 
     static ref URL_TO_CLUSTER: Mutex<HashMap<Url, usize>> = Mutex::new(HashMap::new());
 
-    static ref PRODUCER: Mutex<Producer> = Mutex::new(Producer::from_hosts(vec![KAFKA_URL.to_owned()]).create().unwrap());
+    static ref SAVED_OFFSETS: Mutex<Offsets> = Mutex::new(Offsets::default());
+
+    static ref PRODUCER: Mutex<Producer> = Mutex::new(Producer::from_hosts(CONFIG.kafka.hosts()).create().unwrap());
 }
 
-fn add_document(document: Document) {
+fn add_document(clusters: &mut Vec<Cluster>, fakes: &mut Vec<Fake>, document: Document) {
     // TODO: lookup clusters using position if document on an unit sphere.
     //              O(1) instead of O(n) here!
 
-    let mut clusters = CLUSTERS.lock().unwrap();
-
-    let candidate = find_candidate(&*clusters, &document);
+    let candidate = find_candidate(clusters, &document);
 
     let url = document.entry().url.clone();
 
     let idx = if let Some(idx) = candidate {
         let cluster = &mut clusters[idx];
-        cluster.add_document(document);
+        cluster.add_document(fakes, document);
 
         idx
     } else {
         let mut cluster = Cluster::new();
-        cluster.add_document(document);
+        cluster.add_document(fakes, document);
 
         clusters.push(cluster);
 
@@ -110,66 +165,155 @@ fn find_candidate(clusters: &[Cluster], document: &Document) -> Option<usize> {
     clusters.iter()
         .enumerate()
         .map(|(idx, cluster)| (idx, cluster.distance_to_document(&document)))
-        .filter(|&(_, distance)| distance > 0.7)
+        .filter(|&(_, distance)| distance > CONFIG.compounder.similarity_threshold)
         .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
         .map(|(idx, _)| idx)
 }
 
-fn report_about_fake(document: &Document) {
-    let record = Record::from_value("fakes", serde_json::to_vec(document.entry()).unwrap());
+fn report_about_fake(fakes: &mut Vec<Fake>, document: &Document, verdict: i64, score: f32) {
+    info!("Reporting {} as fake with score {:.3}", document.entry().url, score);
 
-    PRODUCER.lock().unwrap().send(&record).unwrap();
+    fakes.push(Fake {
+        entry: document.entry().clone(),
+        score,
+        verdict
+    });
 }
 
-fn fake_adding() {
-    let mut consumer = Consumer::from_hosts(vec![KAFKA_URL.to_owned()])
+// Fakes are sent at once and snapshots don't keep them, so a restored state sends nothing again.
+// Only after a crash fakes found since the last snapshot are sent twice, the rater keeps the highest score.
+fn send_fakes(fakes: Vec<Fake>) {
+    let mut producer = PRODUCER.lock().unwrap();
+
+    for fake in fakes {
+        let record = Record::from_value(&CONFIG.topics.fakes, serde_json::to_vec(&fake).unwrap());
+        producer.send(&record).unwrap();
+    }
+}
+
+// Offsets of the state are per topic, so topics must have a single partition. Returns it too.
+fn create_consumer(topic: &str) -> (Consumer, i32) {
+    let consumer = Consumer::from_hosts(CONFIG.kafka.hosts())
+        .with_group(CONFIG.compounder.group.clone())
+        .with_offset_storage(GroupOffsetStorage::Kafka)
         .with_fallback_offset(FetchOffset::Earliest)
-        .with_topic("expert".to_owned())
+        .with_topic(topic.to_owned())
         .create().unwrap();
 
+    let partitions = consumer.subscriptions().remove(topic).unwrap_or_else(Vec::new);
+
+    assert_eq!(partitions.len(), 1, "\"{}\" topic must have a single partition", topic);
+
+    let partition = partitions[0];
+
+    (consumer, partition)
+}
+
+// Offsets are committed only after they got into a snapshot, so the consumer resumes from it.
+// `committed` is the last committed offset, the same offset isn't committed twice.
+fn commit_saved(consumer: &mut Consumer, topic: &str, partition: i32, offset: i64, committed: &mut i64) {
+    if offset == *committed {
+        return;
+    }
+
+    consumer.consume_message(topic, partition, offset - 1).unwrap();
+    consumer.commit_consumed().unwrap();
+
+    *committed = offset;
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ExpertMessage {
+    Verdict(Verdict),
+    // Plain urls are still accepted as certain verdicts.
+    Url(String),
+}
+
+fn parse_verdict(value: &[u8]) -> Option<Verdict> {
+    match serde_json::from_slice::<ExpertMessage>(value) {
+        Ok(ExpertMessage::Verdict(verdict)) => Some(verdict),
+        Ok(ExpertMessage::Url(url)) => match Url::parse(&url) {
+            Ok(url) => Some(Verdict { url, score: 1. }),
+            Err(_) => {
+                error!("Unparsable url on \"{}\" topic: {}", CONFIG.topics.expert, url);
+                None
+            }
+        },
+        Err(error) => {
+            error!("Invalid message on \"{}\" topic: {}", CONFIG.topics.expert, error);
+            None
+        }
+    }
+}
+
+fn fake_adding() {
+    let topic = &CONFIG.topics.expert;
+    let (mut consumer, partition) = create_consumer(topic);
+    let restored = STATE.lock().unwrap().offsets.expert;
+    let mut committed = 0;
+
     info!("Waiting for new fakes from expert...");
 
     loop {
         // TODO: rewrite in functional style.
         for message_set in consumer.poll().unwrap().iter() {
             for message in message_set.messages() {
-                let url = match serde_json::from_slice::<String>(message.value) {
-                    Ok(url) => url,
-                    Err(error) => {
-                        error!("Invalid message on \"expert\" topic: {}", error);
-                        continue;
-                    }
-                };
-
-                if let Ok(url) = Url::parse(&url) {
-                    add_fake(url);
-                } else {
-                    error!("Unparsable url on \"expert\" topic: {}", url);
+                // The verdict is already in the restored snapshot.
+                if message.offset < restored {
+                    continue;
                 }
-            }
 
-            consumer.consume_messageset(message_set).unwrap();
+                let verdict = parse_verdict(message.value);
+
+                let mut fakes = Vec::new();
+                let mut state = STATE.lock().unwrap();
+
+                if let Some(verdict) = verdict {
+                    add_fake(&mut state.clusters, &mut fakes, verdict, message.offset);
+                }
+
+                state.offsets.expert = message.offset + 1;
+
+                // Sent under the lock, so a snapshot never has offsets past unsent fakes.
+                send_fakes(fakes);
+            }
         }
 
-        consumer.commit_consumed().unwrap();
+        let saved = SAVED_OFFSETS.lock().unwrap().expert;
+        commit_saved(&mut consumer, topic, partition, saved, &mut committed);
     }
 }
 
-fn add_fake(url: Url) {
-    let map = URL_TO_CLUSTER.lock().unwrap();
+fn add_fake(clusters: &mut [Cluster], fakes: &mut Vec<Fake>, verdict: Verdict, id: i64) {
+    let score = verdict.score.max(0.).min(1.);
+
+    let idx = URL_TO_CLUSTER.lock().unwrap().get(&verdict.url).cloned();
+
+    if let Some(idx) = idx {
+        if let Some(cluster) = clusters.get_mut(idx) {
+            cluster.mark_as_fake(fakes, &verdict.url, id, score);
+        }
+    } else {
+        warn!("Unknown url {} on \"{}\" topic", verdict.url, CONFIG.topics.expert);
+    }
+}
 
-    if let Some(idx) = map.get(&url) {
-        if let Some(cluster) = CLUSTERS.lock().unwrap().get_mut(*idx) {
-            cluster.mark_as_fake();
+fn parse_entry(value: &[u8]) -> Option<Entry> {
+    match serde_json::from_slice::<Entry>(value) {
+        Ok(entry) => Some(entry),
+        Err(error) => {
+            error!("Invalid message on \"{}\" topic: {}", CONFIG.topics.entries, error);
+            None
         }
     }
 }
 
 fn document_adding() {
-    let mut consumer = Consumer::from_hosts(vec![KAFKA_URL.to_owned()])
-        .with_fallback_offset(FetchOffset::Earliest)
-        .with_topic("entries".to_owned())
-        .create().unwrap();
+    let topic = &CONFIG.topics.entries;
+    let (mut consumer, partition) = create_consumer(topic);
+    let restored = STATE.lock().unwrap().offsets.entries;
+    let mut committed = 0;
 
     info!("Waiting for new entries...");
 
@@ -177,30 +321,90 @@ fn document_adding() {
         // TODO: rewrite in functional style.
         for message_set in consumer.poll().unwrap().iter() {
             for message in message_set.messages() {
-                let entry = match serde_json::from_slice::<Entry>(message.value) {
-                    Ok(entry) => entry,
-                    Err(error) => {
-                        error!("Invalid message on \"entries\" topic: {}", error);
-                        continue;
-                    }
-                };
+                // The entry is already in the restored snapshot.
+                if message.offset < restored {
+                    continue;
+                }
 
                 // TODO: parallize it!
-                if let Some(document) = Document::from_entry(entry) {
-                    add_document(document);
+                let document = parse_entry(message.value).and_then(Document::from_entry);
+
+                let mut fakes = Vec::new();
+                let mut state = STATE.lock().unwrap();
+
+                if let Some(document) = document {
+                    add_document(&mut state.clusters, &mut fakes, document);
                 }
+
+                state.offsets.entries = message.offset + 1;
+
+                // Sent under the lock, so a snapshot never has offsets past unsent fakes.
+                send_fakes(fakes);
             }
+        }
+
+        let saved = SAVED_OFFSETS.lock().unwrap().entries;
+        commit_saved(&mut consumer, topic, partition, saved, &mut committed);
+    }
+}
+
+fn restore() {
+    let state = match snapshot::load::<_, State>(&CONFIG.compounder.snapshot_file).unwrap() {
+        Some(state) => state,
+        None => {
+            info!("No snapshot found, starting from scratch");
+            return;
+        }
+    };
+
+    let mut map = URL_TO_CLUSTER.lock().unwrap();
+
+    for (idx, cluster) in state.clusters.iter().enumerate() {
+        for document in &cluster.documents {
+            map.insert(document.entry().url.clone(), idx);
+        }
+    }
+
+    info!("Restored {} clusters at {:?}", state.clusters.len(), state.offsets);
 
-            consumer.consume_messageset(message_set).unwrap();
+    *SAVED_OFFSETS.lock().unwrap() = state.offsets;
+    *STATE.lock().unwrap() = state;
+}
+
+fn snapshotting() {
+    loop {
+        thread::sleep(Duration::from_secs(CONFIG.compounder.snapshot_interval));
+
+        // Processing is blocked while the state is being written.
+        let state = STATE.lock().unwrap();
+
+        if let Err(error) = snapshot::save(&CONFIG.compounder.snapshot_file, &*state) {
+            error!("Cannot save snapshot: {}", error);
+            continue;
         }
 
-        consumer.commit_consumed().unwrap();
+        debug!("Saved snapshot at {:?}", state.offsets);
+
+        *SAVED_OFFSETS.lock().unwrap() = state.offsets;
     }
 }
 
 fn main() {
     logger::init().unwrap();
 
+    restore();
+
+    thread::spawn(snapshotting);
     thread::spawn(fake_adding);
     document_adding();
 }
+
+#[test]
+fn it_restores_without_fakes() {
+    // Older snapshots kept fakes waiting to be sent.
+    let snapshot = r#"{"clusters": [], "offsets": {"entries": 10, "expert": 2}, "fakes": [{"score": 1}]}"#;
+    let state = serde_json::from_str::<State>(snapshot).unwrap();
+
+    assert_eq!((state.offsets.entries, state.offsets.expert), (10, 2));
+    assert_eq!(serde_json::to_value(&state).unwrap().get("fakes"), None);
+}
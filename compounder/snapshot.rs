@@ -0,0 +1,47 @@
+use std::fs::{self, File};
+use std::io::{Write, BufReader, BufWriter, Result as IoResult, Error as IoError, ErrorKind as IoErrorKind};
+use std::path::Path;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+
+pub fn save<P: AsRef<Path>, T: Serialize>(path: P, state: &T) -> IoResult<()> {
+    let path = path.as_ref();
+    let tmp_path = path.with_extension("tmp");
+
+    let mut writer = BufWriter::new(File::create(&tmp_path)?);
+    serde_json::to_writer(&mut writer, state)?;
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+
+    // Replace atomically to survive crashes in the middle of writing.
+    fs::rename(tmp_path, path)
+}
+
+pub fn load<P: AsRef<Path>, T: DeserializeOwned>(path: P) -> IoResult<Option<T>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(ref error) if error.kind() == IoErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(error)
+    };
+
+    serde_json::from_reader(BufReader::new(file))
+        .map(Some)
+        .map_err(|cause| IoError::new(IoErrorKind::InvalidData, cause))
+}
+
+#[test]
+fn it_saves_and_loads() {
+    use std::env;
+
+    let path = env::temp_dir().join("chokurei-snapshot-test.json");
+    let _ = fs::remove_file(&path);
+
+    assert_eq!(load::<_, Vec<(u32, f32)>>(&path).unwrap(), None);
+
+    save(&path, &vec![(1, 0.5), (2, 0.25)]).unwrap();
+    assert_eq!(load::<_, Vec<(u32, f32)>>(&path).unwrap(), Some(vec![(1, 0.5), (2, 0.25)]));
+
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,209 @@
+use kuchiki::{self, NodeRef};
+use kuchiki::traits::*;
+use url::Url;
+
+use common::messages::{Block, BlockKind, Link};
+
+// Contents of these tags aren't text.
+const SKIPPED: &[&str] = &["script", "style", "noscript", "template"];
+
+// Splits HTML into blocks, entities are decoded by the parser.
+pub fn parse(html: &str, base: &Url) -> Vec<Block> {
+    collect(&kuchiki::parse_html().one(html), base)
+}
+
+// Splits the tree into blocks. Blocks inside quotes and list items take their kind, other containers
+// are paragraphs. Links are resolved against `base`, anchors of the same page are dropped.
+pub fn collect(node: &NodeRef, base: &Url) -> Vec<Block> {
+    let mut collector = Collector {
+        blocks: Vec::new(),
+        kinds: Vec::new(),
+        text: String::new(),
+        links: Vec::new(),
+        flushes: 0
+    };
+
+    collector.walk(node, base);
+    collector.flush();
+
+    collector.blocks
+}
+
+struct Collector {
+    blocks: Vec<Block>,
+    // Kinds of open blocks.
+    kinds: Vec<BlockKind>,
+    text: String,
+    links: Vec<Link>,
+    // Links don't cross blocks, so a link is dropped if a block ends inside it.
+    flushes: usize
+}
+
+impl Collector {
+    fn walk(&mut self, node: &NodeRef, base: &Url) {
+        if let Some(text) = node.as_text() {
+            self.text.push_str(&text.borrow());
+            return;
+        }
+
+        let element = match node.as_element() {
+            Some(element) => element,
+            None => {
+                self.walk_children(node, base);
+                return;
+            }
+        };
+
+        let name = &*element.name.local;
+
+        if SKIPPED.contains(&name) {
+            return;
+        }
+
+        if name == "br" {
+            self.text.push(' ');
+            return;
+        }
+
+        if name == "a" {
+            let url = element.attributes.borrow().get("href").and_then(|href| resolve(base, href));
+            let (start, flushes) = (self.text.len(), self.flushes);
+
+            self.walk_children(node, base);
+
+            if let Some(url) = url {
+                let text = if self.flushes == flushes { collapse(&self.text[start..]) } else { String::new() };
+
+                if !text.is_empty() {
+                    self.links.push(Link { url, text });
+                }
+            }
+
+            return;
+        }
+
+        match block_kind(name) {
+            Some(kind) => {
+                self.flush();
+                self.kinds.push(kind);
+                self.walk_children(node, base);
+                self.flush();
+                self.kinds.pop();
+            },
+            None => self.walk_children(node, base)
+        }
+    }
+
+    fn walk_children(&mut self, node: &NodeRef, base: &Url) {
+        for child in node.children() {
+            self.walk(&child, base);
+        }
+    }
+
+    fn flush(&mut self) {
+        let text = collapse(&self.text);
+        self.text.clear();
+        self.flushes += 1;
+
+        let links = self.links.drain(..).collect();
+
+        if text.is_empty() {
+            return;
+        }
+
+        let kind = self.kinds.iter().rev()
+            .cloned()
+            .find(|&kind| kind != BlockKind::Paragraph)
+            .unwrap_or(BlockKind::Paragraph);
+
+        self.blocks.push(Block { kind, text, links });
+    }
+}
+
+fn block_kind(name: &str) -> Option<BlockKind> {
+    match name {
+        "p" | "div" | "section" | "article" | "pre" | "figcaption" | "td" => Some(BlockKind::Paragraph),
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => Some(BlockKind::Heading),
+        "blockquote" => Some(BlockKind::Quote),
+        "li" | "dt" | "dd" => Some(BlockKind::ListItem),
+        _ => None
+    }
+}
+
+fn resolve(base: &Url, href: &str) -> Option<Url> {
+    let href = href.trim();
+
+    if href.is_empty() || href.starts_with('#') {
+        return None;
+    }
+
+    let url = base.join(href).ok()?;
+
+    if url.scheme() == "http" || url.scheme() == "https" { Some(url) } else { None }
+}
+
+fn collapse(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+fn blocks(html: &str) -> Vec<(BlockKind, String)> {
+    let base = Url::parse("http://example.com/news/1").unwrap();
+    parse(html, &base).into_iter().map(|block| (block.kind, block.text)).collect()
+}
+
+#[test]
+fn it_splits_blocks() {
+    let html = "<div><h2>Title</h2><!-- <p>hidden</p> --><p>First <b>paragraph</b>.</p>\
+                <blockquote><p>Quoted</p><p>twice</p></blockquote>\
+                <ul><li>One</li><li><p>Two</p></li></ul>Tail<br>text<SCRIPT>var x = '<p>';</Script></div>";
+
+    assert_eq!(blocks(html), vec![
+        (BlockKind::Heading, "Title".to_owned()),
+        (BlockKind::Paragraph, "First paragraph.".to_owned()),
+        (BlockKind::Quote, "Quoted".to_owned()),
+        (BlockKind::Quote, "twice".to_owned()),
+        (BlockKind::ListItem, "One".to_owned()),
+        (BlockKind::ListItem, "Two".to_owned()),
+        (BlockKind::Paragraph, "Tail text".to_owned())
+    ]);
+
+    assert_eq!(blocks("Plain text"), vec![(BlockKind::Paragraph, "Plain text".to_owned())]);
+    assert!(blocks("<p> </p><!-- comment -->").is_empty());
+}
+
+#[test]
+fn it_keeps_links() {
+    let base = Url::parse("http://example.com/news/1").unwrap();
+    let html = "<p>See <a href=\"/news/2?a=1&amp;b=2\">the <i>previous</i> story</a>, \
+                <a href='#top'>top</a> and <A HREF=https://other.com>other</a>.</p>\
+                <p><a href=\"mailto:editor@example.com\">Write us</a></p>";
+
+    let blocks = parse(html, &base);
+
+    assert_eq!(blocks.len(), 2);
+    assert_eq!(blocks[0].text, "See the previous story, top and other.");
+
+    let links = blocks[0].links.iter()
+        .map(|link| (link.url.as_str(), link.text.as_str()))
+        .collect::<Vec<_>>();
+
+    assert_eq!(links, vec![
+        ("http://example.com/news/2?a=1&b=2", "the previous story"),
+        ("https://other.com/", "other")
+    ]);
+
+    assert!(blocks[1].links.is_empty());
+}
+
+#[test]
+fn it_decodes_entities() {
+    assert_eq!(blocks("<p>&laquo;Q&amp;A&raquo; &#8212; &#x41;&hearts; &unknown; & so</p>"),
+               vec![(BlockKind::Paragraph, "«Q&A» — A♥ &unknown; & so".to_owned())]);
+}
+
+#[test]
+fn it_keeps_inline_quotes() {
+    assert_eq!(blocks("<p>He said <q>hi</q> today</p>"),
+               vec![(BlockKind::Paragraph, "He said hi today".to_owned())]);
+}
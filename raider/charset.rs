@@ -0,0 +1,169 @@
+use encoding::{DecoderTrap, EncodingRef};
+use encoding::all::{UTF_8, UTF_16BE, UTF_16LE, WINDOWS_1251, WINDOWS_1252, KOI8_R};
+use encoding::label::encoding_from_whatwg_label;
+
+// Declarations are expected at the beginning of the document.
+const PRESCAN_LIMIT: usize = 1024;
+
+// The most frequent lowercase letters of Russian texts.
+const FREQUENT_CYRILLIC: &str = "оеаинтсрвлкмдпу";
+
+// Transcodes the body to UTF-8, malformed sequences are replaced.
+pub fn decode(content_type: Option<&str>, body: &[u8]) -> String {
+    let (encoding, body) = detect(content_type, body);
+
+    if encoding.name() == UTF_8.name() {
+        return String::from_utf8_lossy(body).into_owned();
+    }
+
+    encoding.decode(body, DecoderTrap::Replace).unwrap()
+}
+
+// The order follows browsers: BOM, the header, declarations inside and then sniffing.
+fn detect<'a>(content_type: Option<&str>, body: &'a [u8]) -> (EncodingRef, &'a [u8]) {
+    if let Some((encoding, bom)) = from_bom(body) {
+        return (encoding, &body[bom..]);
+    }
+
+    let encoding = content_type.and_then(from_content_type)
+        .or_else(|| from_markup(body))
+        .unwrap_or_else(|| sniff(body));
+
+    (encoding, body)
+}
+
+fn from_bom(body: &[u8]) -> Option<(EncodingRef, usize)> {
+    if body.starts_with(b"\xef\xbb\xbf") {
+        Some((UTF_8 as EncodingRef, 3))
+    } else if body.starts_with(b"\xff\xfe") {
+        Some((UTF_16LE as EncodingRef, 2))
+    } else if body.starts_with(b"\xfe\xff") {
+        Some((UTF_16BE as EncodingRef, 2))
+    } else {
+        None
+    }
+}
+
+fn from_content_type(content_type: &str) -> Option<EncodingRef> {
+    content_type.split(';')
+        .skip(1)
+        .filter_map(|param| {
+            let mut pair = param.splitn(2, '=');
+            let name = pair.next()?.trim();
+            let value = pair.next()?.trim().trim_matches(|c: char| c == '"' || c == '\'');
+
+            if name.eq_ignore_ascii_case("charset") { Some(value) } else { None }
+        })
+        .next()
+        .and_then(from_label)
+}
+
+// Covers `<?xml encoding="..."?>`, `<meta charset="...">` and `<meta http-equiv content="...; charset=...">`.
+fn from_markup(body: &[u8]) -> Option<EncodingRef> {
+    let head = &body[..body.len().min(PRESCAN_LIMIT)];
+    let head = String::from_utf8_lossy(head).to_lowercase();
+
+    if head.trim_left().starts_with("<?xml") {
+        let end = head.find("?>").unwrap_or_else(|| head.len());
+
+        if let Some(label) = attribute_value(&head[..end], "encoding") {
+            return from_label(&label);
+        }
+    }
+
+    head.match_indices("<meta")
+        .filter_map(|(start, _)| {
+            let end = head[start..].find('>').map_or(head.len(), |end| start + end);
+            attribute_value(&head[start..end], "charset")
+        })
+        .filter_map(|label| from_label(&label))
+        .next()
+}
+
+// Finds `name=value`, where the value can be quoted. Also works inside `content="...; charset=..."`.
+fn attribute_value(tag: &str, name: &str) -> Option<String> {
+    let mut rest = tag;
+
+    while let Some(pos) = rest.find(name) {
+        rest = rest[pos + name.len()..].trim_left();
+
+        if !rest.starts_with('=') {
+            continue;
+        }
+
+        let value = rest[1..].trim_left().trim_left_matches(|c: char| c == '"' || c == '\'');
+        let end = value.find(|c: char| c == '"' || c == '\'' || c == ';' || c == '>' || c.is_whitespace())
+            .unwrap_or_else(|| value.len());
+
+        if end > 0 {
+            return Some(value[..end].to_owned());
+        }
+    }
+
+    None
+}
+
+fn from_label(label: &str) -> Option<EncodingRef> {
+    let encoding = encoding_from_whatwg_label(label.trim());
+
+    if encoding.is_none() {
+        debug!("  Unknown charset \"{}\"", label);
+    }
+
+    encoding
+}
+
+// Nothing is declared, so check UTF-8 and guess between popular Cyrillic encodings.
+fn sniff(body: &[u8]) -> EncodingRef {
+    if ::std::str::from_utf8(body).is_ok() {
+        return UTF_8;
+    }
+
+    let candidates: [EncodingRef; 2] = [WINDOWS_1251, KOI8_R];
+
+    candidates.iter()
+        .map(|&encoding| (encoding, cyrillic_score(encoding, body)))
+        .filter(|&(_, score)| score > 0)
+        .max_by_key(|&(_, score)| score)
+        .map_or(WINDOWS_1252 as EncodingRef, |(encoding, _)| encoding)
+}
+
+// Encodings that are wrong for the text give mostly uppercase and rare letters.
+fn cyrillic_score(encoding: EncodingRef, body: &[u8]) -> usize {
+    let text = match encoding.decode(body, DecoderTrap::Replace) {
+        Ok(text) => text,
+        Err(_) => return 0
+    };
+
+    text.chars().filter(|&c| FREQUENT_CYRILLIC.contains(c)).count()
+}
+
+#[test]
+fn it_uses_declared_charset() {
+    // "Привет" in windows-1251.
+    let body = b"<html><head><meta charset=\"windows-1251\"></head><body>\xcf\xf0\xe8\xe2\xe5\xf2</body></html>";
+    assert!(decode(None, body).contains("Привет"));
+
+    let body = b"<html><head><meta http-equiv=\"Content-Type\" content=\"text/html; charset=koi8-r\"></head>\
+                 <body>\xf0\xd2\xc9\xd7\xc5\xd4</body></html>";
+    assert!(decode(None, body).contains("Привет"));
+
+    let body = b"<?xml version=\"1.0\" encoding=\"windows-1251\"?><rss><title>\xcf\xf0\xe8\xe2\xe5\xf2</title></rss>";
+    assert!(decode(None, body).contains("Привет"));
+
+    // The header wins over the markup.
+    let body = b"<meta charset=\"utf-8\">\xcf\xf0\xe8\xe2\xe5\xf2";
+    assert!(decode(Some("text/html; charset=\"cp1251\""), body).contains("Привет"));
+}
+
+#[test]
+fn it_sniffs_charset() {
+    assert_eq!(decode(None, "Привет, мир".as_bytes()), "Привет, мир");
+    assert_eq!(decode(None, b"\xef\xbb\xbfhello"), "hello");
+
+    // "Привет, мир" in windows-1251 and koi8-r.
+    assert_eq!(decode(None, b"\xcf\xf0\xe8\xe2\xe5\xf2, \xec\xe8\xf0"), "Привет, мир");
+    assert_eq!(decode(None, b"\xf0\xd2\xc9\xd7\xc5\xd4, \xcd\xc9\xd2"), "Привет, мир");
+
+    assert_eq!(decode(None, b"caf\xe9"), "café");
+}
@@ -0,0 +1,258 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use curl;
+use curl::easy::{Easy, List};
+use futures::{future, Future};
+use tokio_core::reactor::Handle;
+use tokio_curl::{Session, PerformError};
+use url::Url;
+
+use download::FetchError;
+use limiter::Limiter;
+use semaphore::Semaphore;
+
+const USER_AGENT: &str = "Mozilla/5.0 (compatible; chokurei)";
+
+// The limit of bodies in bytes.
+#[derive(Debug, Clone, Copy)]
+pub enum Limit {
+    // Larger bodies are rejected with `FetchError::TooLarge`.
+    Reject(usize),
+    // Larger bodies are cut at the limit.
+    Truncate(usize)
+}
+
+impl Limit {
+    fn size(&self) -> usize {
+        match *self {
+            Limit::Reject(size) | Limit::Truncate(size) => size
+        }
+    }
+}
+
+pub struct Response {
+    status_code: u32,
+    headers: HashMap<String, Vec<String>>,
+    body: Vec<u8>
+}
+
+impl Response {
+    pub fn status_code(&self) -> u32 {
+        self.status_code
+    }
+
+    pub fn is_success(&self) -> bool {
+        200 <= self.status_code && self.status_code < 300
+    }
+
+    pub fn headers(&self) -> &HashMap<String, Vec<String>> {
+        &self.headers
+    }
+
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+}
+
+// All requests go through one curl session, so connections and resolved names are reused.
+#[derive(Clone)]
+pub struct Client {
+    session: Session,
+    connections: Semaphore,
+    max_host_connections: usize,
+    hosts: Rc<RefCell<HashMap<String, Semaphore>>>,
+    limiter: Limiter,
+    dns_cache_ttl: Duration,
+    connect_timeout: Duration,
+    // Covers the whole request, so servers trickling bytes cannot hang it.
+    timeout: Duration
+}
+
+struct Transfer {
+    easy: Easy,
+    headers: Arc<Mutex<Vec<(String, String)>>>,
+    body: Arc<Mutex<Vec<u8>>>
+}
+
+impl Client {
+    // Outcomes of all requests are recorded by the limiter.
+    pub fn new(handle: &Handle, limiter: &Limiter, max_connections: usize, max_host_connections: usize,
+               dns_cache_ttl: Duration, connect_timeout: Duration, timeout: Duration) -> Client
+    {
+        Client {
+            session: Session::new(handle.clone()),
+            connections: Semaphore::new(max_connections),
+            max_host_connections,
+            hosts: Rc::new(RefCell::new(HashMap::new())),
+            limiter: limiter.clone(),
+            dns_cache_ttl,
+            connect_timeout,
+            timeout
+        }
+    }
+
+    // Waits for the host's rate. Requests don't do it themselves to not occupy slots meanwhile.
+    pub fn ready(&self, url: &Url) -> impl Future<Item=(), Error=FetchError> + 'static {
+        self.limiter.acquire(url)
+    }
+
+    // Forgets slots of hosts without requests in flight.
+    pub fn evict_idle(&self) {
+        self.hosts.borrow_mut().retain(|_, slots| !slots.is_idle());
+    }
+
+    pub fn get(&self, url: &Url, headers: &[(&str, String)], limit: Limit)
+        -> impl Future<Item=Response, Error=FetchError> + 'static
+    {
+        let max_size = limit.size();
+
+        let transfer = match self.prepare(url, headers, limit) {
+            Ok(transfer) => transfer,
+            Err(error) => return future::Either::A(future::err(curl_error(error, max_size)))
+        };
+
+        let host = url.host_str().unwrap_or("").to_owned();
+        let max_host_connections = self.max_host_connections;
+
+        let host_slots = self.hosts.borrow_mut()
+            .entry(host)
+            .or_insert_with(|| Semaphore::new(max_host_connections))
+            .clone();
+
+        let session = self.session.clone();
+        let connections = self.connections.clone();
+        let limiter = self.limiter.clone();
+        let url = url.clone();
+
+        let permits = host_slots.acquire()
+            .and_then(move |host_permit| connections.acquire().map(move |permit| (host_permit, permit)))
+            .map_err(|_| FetchError::Network(IoError::new(IoErrorKind::Other, "The client is closed")));
+
+        let response = permits.and_then(move |permits| {
+            let Transfer { easy, headers, body } = transfer;
+
+            session.perform(easy).then(move |result| {
+                drop(permits);
+
+                let result = complete(result, headers, body, max_size);
+                limiter.record(&url, &result);
+
+                result
+            })
+        });
+
+        future::Either::B(response)
+    }
+
+    fn prepare(&self, url: &Url, headers: &[(&str, String)], limit: Limit) -> Result<Transfer, curl::Error> {
+        let mut easy = Easy::new();
+
+        easy.url(url.as_str())?;
+        easy.get(true)?;
+        easy.useragent(USER_AGENT)?;
+        // Any encoding supported by curl, the body is decompressed on the fly.
+        easy.accept_encoding("")?;
+        easy.tcp_keepalive(true)?;
+        easy.dns_cache_timeout(self.dns_cache_ttl)?;
+        easy.connect_timeout(self.connect_timeout)?;
+        easy.timeout(self.timeout)?;
+        // Rejects the response early if Content-Length is known.
+        if let Limit::Reject(max_size) = limit {
+            easy.max_filesize(max_size as u64)?;
+        }
+
+        let mut list = List::new();
+
+        for &(name, ref value) in headers {
+            list.append(&format!("{}: {}", name, value))?;
+        }
+
+        easy.http_headers(list)?;
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let body = Arc::new(Mutex::new(Vec::new()));
+
+        let sink = body.clone();
+        easy.write_function(move |data| {
+            let mut body = sink.lock().unwrap();
+
+            match limit {
+                // Returning less than received aborts the transfer with a write error.
+                Limit::Reject(max_size) if body.len() + data.len() > max_size => return Ok(0),
+                // The rest is read and dropped, an aborted transfer has no status code.
+                Limit::Truncate(max_size) if body.len() + data.len() > max_size => {
+                    let len = max_size.saturating_sub(body.len());
+                    body.extend_from_slice(&data[..len]);
+                },
+                _ => body.extend_from_slice(data)
+            }
+
+            Ok(data.len())
+        })?;
+
+        let sink = received.clone();
+        easy.header_function(move |line| {
+            let line = String::from_utf8_lossy(line);
+            let mut headers = sink.lock().unwrap();
+
+            // Interim responses like "100 Continue" have their own headers.
+            if line.starts_with("HTTP/") {
+                headers.clear();
+            } else if let Some(idx) = line.find(':') {
+                headers.push((line[..idx].trim().to_owned(), line[idx + 1..].trim().to_owned()));
+            }
+
+            true
+        })?;
+
+        Ok(Transfer { easy, headers: received, body })
+    }
+}
+
+fn complete(result: Result<Easy, PerformError>, headers: Arc<Mutex<Vec<(String, String)>>>,
+            body: Arc<Mutex<Vec<u8>>>, max_size: usize) -> Result<Response, FetchError>
+{
+    let mut easy = result.map_err(|error| perform_error(error, max_size))?;
+    let status_code = easy.response_code().map_err(|error| curl_error(error, max_size))?;
+
+    let mut map = HashMap::new();
+
+    for (name, value) in headers.lock().unwrap().drain(..) {
+        map.entry(name).or_insert_with(Vec::new).push(value);
+    }
+
+    let body = body.lock().unwrap().split_off(0);
+
+    Ok(Response { status_code, headers: map, body })
+}
+
+// Errors of curl come wrapped into IO ones.
+fn perform_error(error: PerformError, max_size: usize) -> FetchError {
+    let error = error.into_error();
+
+    if !error.get_ref().map_or(false, |cause| cause.is::<curl::Error>()) {
+        return FetchError::from(error);
+    }
+
+    let cause = error.into_inner().unwrap().downcast::<curl::Error>().unwrap();
+
+    curl_error(*cause, max_size)
+}
+
+fn curl_error(error: curl::Error, max_size: usize) -> FetchError {
+    if error.is_operation_timedout() {
+        FetchError::Timeout
+    } else if error.is_filesize_exceeded() || error.is_write_error() {
+        // Only the body callback refuses to write.
+        FetchError::TooLarge(max_size)
+    } else if error.is_bad_content_encoding() {
+        FetchError::Decode(IoError::new(IoErrorKind::InvalidData, error))
+    } else {
+        FetchError::Network(IoError::new(IoErrorKind::Other, error))
+    }
+}
@@ -0,0 +1,244 @@
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use time::Timespec;
+
+// Formats are tried in order after normalization: weekdays and commas are removed, months are
+// translated to English abbreviations and the zone is cut off.
+const DATE_TIME_FORMATS: &[&str] = &[
+    // "17 May 2017 05:02:12", RFC 822 without the zone. `%Y` accepts two digits, so `%y` goes first.
+    "%d %b %y %H:%M:%S",
+    "%d %b %y %H:%M",
+    "%d %b %Y %H:%M:%S",
+    "%d %b %Y %H:%M",
+    // "May 17 2017 05:02:12" and asctime's "May 17 05:02:12 2017".
+    "%b %d %Y %H:%M:%S",
+    "%b %d %Y %H:%M",
+    "%b %d %H:%M:%S %Y",
+    // ISO 8601 without the zone, fractions are optional.
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%d %H:%M",
+    "%Y/%m/%d %H:%M:%S",
+    "%Y/%m/%d %H:%M",
+    // "17.05.2017 05:02".
+    "%d.%m.%Y %H:%M:%S",
+    "%d.%m.%Y %H:%M"
+];
+
+// Dates without time mean midnight.
+const DATE_FORMATS: &[&str] = &["%d %b %Y", "%b %d %Y", "%Y-%m-%d", "%Y/%m/%d", "%d.%m.%Y"];
+
+const MONTHS: &[(&str, &[&str])] = &[
+    ("Jan", &["jan", "january", "янв", "январь", "января"]),
+    ("Feb", &["feb", "february", "фев", "февр", "февраль", "февраля"]),
+    ("Mar", &["mar", "march", "мар", "март", "марта"]),
+    ("Apr", &["apr", "april", "апр", "апрель", "апреля"]),
+    ("May", &["may", "май", "мая"]),
+    ("Jun", &["jun", "june", "июн", "июнь", "июня"]),
+    ("Jul", &["jul", "july", "июл", "июль", "июля"]),
+    ("Aug", &["aug", "august", "авг", "август", "августа"]),
+    ("Sep", &["sep", "sept", "september", "сен", "сент", "сентябрь", "сентября"]),
+    ("Oct", &["oct", "october", "окт", "октябрь", "октября"]),
+    ("Nov", &["nov", "november", "ноя", "нояб", "ноябрь", "ноября"]),
+    ("Dec", &["dec", "december", "дек", "декабрь", "декабря"])
+];
+
+// Weekdays are often wrong, so they are ignored as well as Russian "г." (year) and "в" (at).
+const IGNORED: &[&str] = &[
+    "mon", "tue", "wed", "thu", "fri", "sat", "sun",
+    "monday", "tuesday", "wednesday", "thursday", "friday", "saturday", "sunday",
+    "пн", "вт", "ср", "чт", "пт", "сб", "вс",
+    "понедельник", "вторник", "среда", "четверг", "пятница", "суббота", "воскресенье",
+    "г", "года", "в"
+];
+
+// Offsets of zone abbreviations in hours.
+const ZONES: &[(&str, i32)] = &[
+    ("gmt", 0), ("ut", 0), ("utc", 0), ("z", 0),
+    ("est", -5), ("edt", -4), ("cst", -6), ("cdt", -5),
+    ("mst", -7), ("mdt", -6), ("pst", -8), ("pdt", -7),
+    ("cet", 1), ("cest", 2), ("eet", 2), ("eest", 3), ("msk", 3)
+];
+
+// Returns the time and the offset (in seconds east of UTC) if the date has a zone. Dates without
+// zones are considered to be in `default_offset`.
+pub fn parse(date: &str, default_offset: i32) -> Option<(Timespec, Option<i32>)> {
+    let date = date.trim();
+
+    // Fast paths for well-formed dates.
+    if let Ok(date) = DateTime::parse_from_rfc3339(date).or_else(|_| DateTime::parse_from_rfc2822(date)) {
+        return Some((Timespec::new(date.timestamp(), 0), Some(date.offset().local_minus_utc())));
+    }
+
+    let (date, offset) = normalize(date);
+
+    let datetime = DATE_TIME_FORMATS.iter()
+        .filter_map(|format| NaiveDateTime::parse_from_str(&date, format).ok())
+        .next()
+        .or_else(|| DATE_FORMATS.iter()
+            .filter_map(|format| NaiveDate::parse_from_str(&date, format).ok())
+            .map(|date| date.and_hms(0, 0, 0))
+            .next())?;
+
+    let sec = datetime.timestamp() - i64::from(offset.unwrap_or(default_offset));
+
+    Some((Timespec::new(sec, 0), offset))
+}
+
+fn normalize(date: &str) -> (String, Option<i32>) {
+    let mut tokens = date.split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|token| !token.is_empty())
+        .collect::<Vec<_>>();
+
+    let mut offset = None;
+
+    if let Some(last) = tokens.pop() {
+        let (rest, zone) = split_zone(last);
+
+        match zone.and_then(parse_zone) {
+            Some(zone) => {
+                offset = Some(zone);
+
+                if !rest.is_empty() {
+                    tokens.push(rest);
+                }
+            },
+            None => tokens.push(last)
+        }
+    }
+
+    let tokens = tokens.into_iter()
+        .map(|token| (token, token.trim_right_matches('.').to_lowercase()))
+        .filter(|&(_, ref lower)| !IGNORED.contains(&lower.as_str()))
+        .map(|(token, lower)| match MONTHS.iter().find(|&&(_, names)| names.contains(&lower.as_str())) {
+            Some(&(month, _)) => month,
+            None => token
+        })
+        .collect::<Vec<_>>();
+
+    (tokens.join(" "), offset)
+}
+
+// Cuts a zone glued to the time: "05:02:12+0300", "2017-05-17T05:02:12Z". Separate zones are
+// returned as they are.
+fn split_zone(token: &str) -> (&str, Option<&str>) {
+    if !token.contains(':') || token.starts_with(char::is_alphabetic) {
+        return ("", Some(token));
+    }
+
+    // Skip the date part, it has dashes.
+    let start = token.find(|c: char| c == 'T' || c == 't').map_or(0, |idx| idx + 1);
+
+    if let Some(idx) = token[start..].find(|c: char| c == '+' || c == '-') {
+        return (&token[..start + idx], Some(&token[start + idx..]));
+    }
+
+    if token.ends_with('Z') || token.ends_with('z') {
+        return (&token[..token.len() - 1], Some("Z"));
+    }
+
+    (token, None)
+}
+
+// Accepts abbreviations, "+0300", "+03:00", "+03" and "GMT+3".
+fn parse_zone(zone: &str) -> Option<i32> {
+    let zone = zone.to_lowercase();
+
+    if let Some(&(_, hours)) = ZONES.iter().find(|&&(name, _)| name == zone) {
+        return Some(hours * 3600);
+    }
+
+    let zone = zone.trim_left_matches(|c: char| c.is_alphabetic());
+
+    let sign = match zone.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None
+    };
+
+    let digits = zone[1..].replace(':', "");
+
+    if digits.is_empty() || digits.len() > 4 || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let (hours, minutes) = if digits.len() <= 2 {
+        (digits.parse::<i32>().ok()?, 0)
+    } else {
+        let split = digits.len() - 2;
+        (digits[..split].parse::<i32>().ok()?, digits[split..].parse::<i32>().ok()?)
+    };
+
+    if hours > 14 || minutes > 59 {
+        return None;
+    }
+
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+#[cfg(test)]
+macro_rules! test {
+    ($date:expr, $expected:expr) => {
+        assert_eq!(parse($date, 0).map(|(time, _)| time.sec), $expected, "{}", $date);
+    }
+}
+
+#[test]
+fn it_parses_rfc822() {
+    // Wed, 17 May 2017 05:02:12 GMT.
+    let expected = Some(1494997332);
+
+    test!("Wed, 17 May 2017 05:02:12 +0000", expected);
+    test!("Wed, 17 May 2017 05:02:12 GMT", expected);
+    test!("17 May 2017 08:02:12 +0300", expected);
+    test!("Wed, 17 May 2017 01:02:12 EDT", expected);
+    test!("Sun, 17 May 2017 05:02:12 UT", expected);
+    test!("Wednesday, 17 May 17 05:02:12 GMT", expected);
+    test!("Wed, 17 May 2017 08:02:12 MSK", expected);
+    test!("Wed May 17 05:02:12 2017", expected);
+    test!("May 17, 2017 05:02:12 GMT+00:00", expected);
+    test!("Wed, 17 May 2017 05:02 GMT", Some(1494997320));
+}
+
+#[test]
+fn it_parses_iso8601() {
+    let expected = Some(1494997332);
+
+    test!("2017-05-17T08:02:12+03:00", expected);
+    test!("2017-05-17T05:02:12Z", expected);
+    test!("2017-05-17T05:02:12.345Z", expected);
+    test!("2017-05-17T08:02:12+0300", expected);
+    test!("2017-05-17T00:02:12-05", expected);
+    test!("2017-05-17 05:02:12", expected);
+    test!("2017-05-17 08:02:12 +03:00", expected);
+    test!("2017/05/17 05:02:12", expected);
+    test!("2017-05-17", Some(1494979200));
+}
+
+#[test]
+fn it_parses_russian_dates() {
+    let expected = Some(1494997320);
+
+    test!("17 мая 2017 г. в 05:02", expected);
+    test!("Среда, 17 Мая 2017 08:02 +0300", expected);
+    test!("17.05.2017 05:02", expected);
+    test!("17 май 2017", Some(1494979200));
+    test!("1 сент. 2017", Some(1504224000));
+}
+
+#[test]
+fn it_uses_default_offset() {
+    let date = "2017-05-17 08:02:12";
+
+    assert_eq!(parse(date, 3 * 3600), Some((Timespec::new(1494997332, 0), None)));
+    assert_eq!(parse("2017-05-17T05:02:12Z", 3 * 3600), Some((Timespec::new(1494997332, 0), Some(0))));
+    assert_eq!(parse("17 May 2017 08:02:12 +0300", 0), Some((Timespec::new(1494997332, 0), Some(3 * 3600))));
+}
+
+#[test]
+fn it_rejects_garbage() {
+    test!("", None);
+    test!("yesterday", None);
+    test!("17 Foo 2017", None);
+    test!("2017-13-45", None);
+}
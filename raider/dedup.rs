@@ -0,0 +1,105 @@
+use std::fs::{self, File};
+use std::io::{Write, BufReader, BufWriter, Result as IoResult, ErrorKind as IoErrorKind};
+use std::mem;
+use std::path::{Path, PathBuf};
+
+use url::Url;
+
+use common::bloom::BloomFilter;
+use common::key::Key;
+
+// Two generations of filters are used to forget old keys without losing recent ones.
+pub struct Deduplicator {
+    path: PathBuf,
+    capacity: usize,
+    fp_rate: f64,
+    current: BloomFilter,
+    previous: BloomFilter
+}
+
+impl Deduplicator {
+    pub fn open<P: AsRef<Path>>(path: P, capacity: usize, fp_rate: f64) -> IoResult<Deduplicator> {
+        let path = path.as_ref().to_owned();
+
+        let (current, previous) = match File::open(&path) {
+            Ok(file) => {
+                let mut reader = BufReader::new(file);
+                (BloomFilter::read_from(&mut reader)?, BloomFilter::read_from(&mut reader)?)
+            },
+            Err(ref error) if error.kind() == IoErrorKind::NotFound => {
+                (BloomFilter::new(capacity, fp_rate), BloomFilter::new(capacity, fp_rate))
+            },
+            Err(error) => return Err(error)
+        };
+
+        Ok(Deduplicator { path, capacity, fp_rate, current, previous })
+    }
+
+    // Returns `false` if the url is (probably) seen before.
+    pub fn insert(&mut self, url: &Url) -> bool {
+        let key = Key::from(url.clone());
+
+        if self.previous.contains(&key) || !self.current.insert(&key) {
+            return false;
+        }
+
+        if self.current.len() >= self.capacity {
+            info!("Rotating the dedup filter after {} keys", self.current.len());
+
+            let fresh = BloomFilter::new(self.capacity, self.fp_rate);
+            self.previous = mem::replace(&mut self.current, fresh);
+        }
+
+        true
+    }
+
+    pub fn save(&self) -> IoResult<()> {
+        let tmp_path = self.path.with_extension("tmp");
+
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        self.current.write_to(&mut writer)?;
+        self.previous.write_to(&mut writer)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+
+        // Replace atomically to survive crashes in the middle of writing.
+        fs::rename(tmp_path, &self.path)
+    }
+}
+
+#[test]
+fn it_rotates_generations() {
+    use std::env;
+
+    let path = env::temp_dir().join("chokurei-dedup-test.bloom");
+    let _ = fs::remove_file(&path);
+
+    let url = |idx| Url::parse(&format!("http://example.com/{}", idx)).unwrap();
+
+    let mut dedup = Deduplicator::open(&path, 10, 0.001).unwrap();
+
+    assert!(dedup.insert(&url(0)));
+    assert!(!dedup.insert(&url(0)));
+    assert!(!dedup.insert(&Url::parse("https://www.example.com/0/").unwrap()));
+
+    for idx in 1..10 {
+        dedup.insert(&url(idx));
+    }
+
+    // The first generation is still remembered.
+    assert!(!dedup.insert(&url(0)));
+
+    dedup.save().unwrap();
+    let mut dedup = Deduplicator::open(&path, 10, 0.001).unwrap();
+
+    assert!(!dedup.insert(&url(5)));
+
+    for idx in 10..20 {
+        dedup.insert(&url(idx));
+    }
+
+    // Now it's forgotten.
+    assert!(dedup.insert(&url(0)));
+
+    fs::remove_file(&path).unwrap();
+}
@@ -1,44 +1,311 @@
+use std::cmp;
+use std::error::Error;
+use std::fmt;
 use std::io::{Error as IoError, ErrorKind as IoErrorKind};
 use std::str;
+
+use mailparse;
+use time;
+use futures::future::{self, Either};
 use futures::Future;
-use tokio_core::reactor::Handle;
-use tokio_request::get;
-use rss::Channel;
 use url::Url;
 
-const USER_AGENT: &str = "Mozilla/5.0 (compatible; chokurei)";
-
-// TODO: share a session between requests.
-// TODO: a bad http status code isn't IO error.
-
-pub fn channel(handle: &Handle, url: &Url) -> impl Future<Item=Channel, Error=IoError> + 'static {
-    get(url)
-        .header("User-Agent", USER_AGENT)
-        .send(handle.clone())
-        .and_then(|response| {
-            if !response.is_success() {
-                let cause = format!("Bad status code: {}", response.status_code());
-                return Err(IoError::new(IoErrorKind::Other, cause));
-            }
-
-            Channel::read_from(response.body())
-                .map_err(|cause| IoError::new(IoErrorKind::InvalidData, cause))
-        })
-}
-
-pub fn document(handle: &Handle, url: &Url) -> impl Future<Item=String, Error=IoError> + 'static {
-    get(url)
-        .header("User-Agent", USER_AGENT)
-        .send(handle.clone())
-        .and_then(|response| {
-            if !response.is_success() {
-                let cause = format!("Bad status code: {}", response.status_code());
-                return Err(IoError::new(IoErrorKind::Other, cause));
-            }
-
-            let buffer = Vec::from(response);
-
-            String::from_utf8(buffer)
-                .map_err(|cause| IoError::new(IoErrorKind::InvalidData, cause))
-        })
+use charset;
+use client::{Client, Response, Limit};
+use feed::{self, Channel};
+
+const MAX_REDIRECTS: u32 = 5;
+// Like Google, only the first 500 KiB of robots.txt are parsed, the rest is ignored.
+const MAX_ROBOTS_SIZE: usize = 500 * 1024;
+
+#[derive(Debug)]
+pub enum FetchError {
+    // `retry_after` is in seconds, it's sent with 429 and 503 statuses.
+    Status { code: u32, retry_after: Option<u32> },
+    Network(IoError),
+    Timeout,
+    // The limit in bytes.
+    TooLarge(usize),
+    // The body is broken on the transport level (compression, chunks and so on).
+    Decode(IoError),
+    Parse(String),
+    Redirect(String),
+    // The host is blocked for the number of seconds after failures or by `Retry-After`.
+    Blocked(u32)
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FetchError::Status { code, retry_after: Some(secs) } =>
+                write!(f, "Bad status code: {}, retry after {}s", code, secs),
+            FetchError::Status { code, retry_after: None } => write!(f, "Bad status code: {}", code),
+            FetchError::Network(ref error) => write!(f, "Network error: {}", error),
+            FetchError::Timeout => write!(f, "Timed out"),
+            FetchError::TooLarge(limit) => write!(f, "The body exceeds {} bytes", limit),
+            FetchError::Decode(ref error) => write!(f, "Cannot decode the body: {}", error),
+            FetchError::Parse(ref cause) => write!(f, "Cannot parse: {}", cause),
+            FetchError::Redirect(ref cause) => write!(f, "Bad redirect: {}", cause),
+            FetchError::Blocked(secs) => write!(f, "The host is blocked for {}s", secs)
+        }
+    }
+}
+
+impl Error for FetchError {
+    fn description(&self) -> &str {
+        match *self {
+            FetchError::Status { .. } => "bad status code",
+            FetchError::Network(_) => "network error",
+            FetchError::Timeout => "timed out",
+            FetchError::TooLarge(_) => "too large body",
+            FetchError::Decode(_) => "cannot decode the body",
+            FetchError::Parse(_) => "cannot parse",
+            FetchError::Redirect(_) => "bad redirect",
+            FetchError::Blocked(_) => "the host is blocked"
+        }
+    }
+}
+
+impl FetchError {
+    // Transient errors are likely to go away soon, others are repeated on retries.
+    pub fn is_transient(&self) -> bool {
+        match *self {
+            FetchError::Status { code, .. } => code == 429 || code >= 500,
+            FetchError::Network(_) | FetchError::Timeout | FetchError::Blocked(_) => true,
+            _ => false
+        }
+    }
+}
+
+impl From<IoError> for FetchError {
+    fn from(error: IoError) -> FetchError {
+        match error.kind() {
+            IoErrorKind::TimedOut => FetchError::Timeout,
+            IoErrorKind::InvalidData => FetchError::Decode(error),
+            _ => FetchError::Network(error)
+        }
+    }
+}
+
+// HTTP validators used to make conditional requests.
+#[derive(Debug, Default)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>
+}
+
+pub struct ChannelResponse {
+    // `None` if the channel isn't modified since the previous request.
+    pub channel: Option<Channel>,
+    pub validators: Validators,
+    // The new url if the channel is moved permanently.
+    pub moved_to: Option<Url>
+}
+
+struct Redirected {
+    response: Response,
+    url: Url,
+    // Whether all redirects on the way were permanent.
+    permanent: bool
+}
+
+pub fn channel(client: &Client, url: &Url, validators: &Validators, max_size: usize)
+    -> impl Future<Item=ChannelResponse, Error=FetchError> + 'static
+{
+    let mut headers = Vec::new();
+
+    if let Some(ref etag) = validators.etag {
+        headers.push(("If-None-Match", etag.clone()));
+    }
+
+    if let Some(ref last_modified) = validators.last_modified {
+        headers.push(("If-Modified-Since", last_modified.clone()));
+    }
+
+    let original = url.clone();
+
+    send(client, url, headers, Limit::Reject(max_size)).and_then(move |redirected| {
+        let response = redirected.response;
+
+        let moved_to = if redirected.permanent && redirected.url != original {
+            Some(redirected.url)
+        } else {
+            None
+        };
+
+        let validators = Validators {
+            etag: header(&response, "ETag").map(str::to_owned),
+            last_modified: header(&response, "Last-Modified").map(str::to_owned)
+        };
+
+        if response.status_code() == 304 {
+            return Ok(ChannelResponse { channel: None, validators, moved_to });
+        }
+
+        if !response.is_success() {
+            return Err(status_error(&response));
+        }
+
+        let content_type = header(&response, "Content-Type");
+        let body = charset::decode(content_type, response.body());
+
+        let channel = feed::parse(content_type, body.as_bytes())
+            .map_err(FetchError::Parse)?;
+
+        Ok(ChannelResponse { channel: Some(channel), validators, moved_to })
+    })
+}
+
+pub fn document(client: &Client, url: &Url, max_size: usize)
+    -> impl Future<Item=String, Error=FetchError> + 'static
+{
+    send(client, url, Vec::new(), Limit::Reject(max_size)).and_then(|redirected| {
+        let response = redirected.response;
+
+        if !response.is_success() {
+            return Err(status_error(&response));
+        }
+
+        Ok(charset::decode(header(&response, "Content-Type"), response.body()))
+    })
+}
+
+// robots.txt is fetched on demand, so it waits for the host's rate here.
+pub fn robots(client: &Client, url: &Url) -> impl Future<Item=Option<String>, Error=FetchError> + 'static {
+    let (client, url) = (client.clone(), url.clone());
+
+    let request = client.ready(&url)
+        .and_then(move |_| send(&client, &url, Vec::new(), Limit::Truncate(MAX_ROBOTS_SIZE)));
+
+    request.and_then(|redirected| {
+        let response = redirected.response;
+
+        // There are no restrictions if robots.txt is missing.
+        if is_robots_missing(response.status_code()) {
+            return Ok(None);
+        }
+
+        if !response.is_success() {
+            return Err(status_error(&response));
+        }
+
+        Ok(Some(String::from_utf8_lossy(response.body()).into_owned()))
+    })
+}
+
+// Like Google, 429 means that robots.txt is unreachable as 5xx do, not that it's missing.
+fn is_robots_missing(status: u32) -> bool {
+    400 <= status && status < 500 && status != 429
+}
+
+fn send(client: &Client, url: &Url, headers: Vec<(&'static str, String)>, limit: Limit)
+    -> Box<Future<Item=Redirected, Error=FetchError>>
+{
+    send_hop(client.clone(), url.clone(), headers, limit, 0, true)
+}
+
+fn send_hop(client: Client, url: Url, headers: Vec<(&'static str, String)>, limit: Limit, hop: u32,
+            permanent: bool) -> Box<Future<Item=Redirected, Error=FetchError>>
+{
+    let request = client.get(&url, &headers, limit);
+
+    Box::new(request.and_then(move |response| {
+        let status = response.status_code();
+
+        let location = match status {
+            301 | 302 | 303 | 307 | 308 => header(&response, "Location").map(str::to_owned),
+            _ => None
+        };
+
+        let location = match location {
+            Some(location) => location,
+            None => return Either::A(future::ok(Redirected { response, url, permanent }))
+        };
+
+        if hop >= MAX_REDIRECTS {
+            let cause = format!("Too many redirects, the last one is to {}", location);
+            return Either::A(future::err(FetchError::Redirect(cause)));
+        }
+
+        let next = match url.join(&location) {
+            Ok(next) => next,
+            Err(cause) => return Either::A(future::err(FetchError::Redirect(cause.to_string())))
+        };
+
+        debug!("  Redirecting from {} to {} ({})", url, next, status);
+
+        let permanent = permanent && (status == 301 || status == 308);
+
+        // Redirects often lead to other hosts, which have their own rates. The first hop waits in callers.
+        let ready = client.ready(&next);
+
+        Either::B(ready.and_then(move |_| send_hop(client, next, headers, limit, hop + 1, permanent)))
+    }))
+}
+
+fn header<'a>(response: &'a Response, name: &str) -> Option<&'a str> {
+    response.headers().iter()
+        .find(|&(key, _)| key.eq_ignore_ascii_case(name))
+        .and_then(|(_, values)| values.first())
+        .map(String::as_str)
+}
+
+fn status_error(response: &Response) -> FetchError {
+    let code = response.status_code();
+
+    let retry_after = match code {
+        429 | 503 => requested_delay(response),
+        _ => None
+    };
+
+    FetchError::Status { code, retry_after }
+}
+
+pub fn requested_delay(response: &Response) -> Option<u32> {
+    header(response, "Retry-After").and_then(parse_retry_after)
+}
+
+// The value is either a number of seconds or an HTTP date.
+fn parse_retry_after(value: &str) -> Option<u32> {
+    let value = value.trim();
+
+    if let Ok(secs) = value.parse::<u32>() {
+        return Some(secs);
+    }
+
+    let date = mailparse::dateparse(value).ok()?;
+    let now = time::get_time().sec;
+
+    Some(cmp::max(date - now, 0) as u32)
+}
+
+#[test]
+fn it_parses_retry_after() {
+    assert_eq!(parse_retry_after("120"), Some(120));
+    assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(0));
+    assert_eq!(parse_retry_after("soon"), None);
+}
+
+#[test]
+fn it_treats_rate_limited_robots_as_unreachable() {
+    assert!(is_robots_missing(404));
+    assert!(is_robots_missing(403));
+    assert!(!is_robots_missing(429));
+    assert!(!is_robots_missing(503));
+    assert!(!is_robots_missing(200));
+}
+
+#[test]
+fn it_tells_transient_errors() {
+    let status = |code| FetchError::Status { code, retry_after: None };
+
+    assert!(status(503).is_transient());
+    assert!(status(429).is_transient());
+    assert!(FetchError::Timeout.is_transient());
+    assert!(FetchError::Blocked(60).is_transient());
+
+    assert!(!status(404).is_transient());
+    assert!(!status(410).is_transient());
+    assert!(!FetchError::TooLarge(1024).is_transient());
+    assert!(!FetchError::Parse("broken".to_owned()).is_transient());
 }
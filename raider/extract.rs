@@ -0,0 +1,137 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use futures::Future;
+use futures_cpupool::CpuPool;
+use readability::Readability;
+use url::Url;
+
+use common::messages::Block;
+
+use blocks;
+use quality;
+use semaphore::Semaphore;
+
+pub struct Extraction {
+    pub text: String,
+    pub blocks: Vec<Block>,
+    // The number of chars inside links, navigation is mostly links.
+    pub linked: usize
+}
+
+#[derive(Default)]
+struct HostStats {
+    count: u32,
+    total: Duration,
+    max: Duration
+}
+
+// Runs Readability on a pool of threads, so large pages don't block the reactor.
+#[derive(Clone)]
+pub struct Extractor {
+    pool: CpuPool,
+    // Bounds the pool's queue, so pages wait here instead of piling up in memory.
+    queue: Semaphore,
+    slow: Duration,
+    stats: Rc<RefCell<HashMap<String, HostStats>>>
+}
+
+impl Extractor {
+    pub fn new(threads: usize, queue: usize, slow: Duration) -> Extractor {
+        Extractor {
+            pool: CpuPool::new(threads),
+            queue: Semaphore::new(threads + queue),
+            slow,
+            stats: Rc::new(RefCell::new(HashMap::new()))
+        }
+    }
+
+    pub fn extract(&self, url: &Url, document: String) -> impl Future<Item=Extraction, Error=()> + 'static {
+        let pool = self.pool.clone();
+        let slow = self.slow;
+        let stats = self.stats.clone();
+        let url = url.clone();
+        let base = url.clone();
+
+        self.queue.acquire().and_then(move |permit| {
+            let extraction = pool.spawn_fn(move || {
+                let start = Instant::now();
+                let node = Readability::new().parse(&document);
+                let blocks = blocks::collect(&node, &base);
+
+                let linked = quality::linked_chars(&node);
+
+                let extraction = Extraction { text: node.text_contents(), blocks, linked };
+
+                Ok::<_, ()>((extraction, start.elapsed()))
+            });
+
+            extraction.map(move |(extraction, elapsed)| {
+                drop(permit);
+
+                if elapsed >= slow {
+                    warn!("Extracting {} took {}ms", url, as_millis(elapsed));
+                }
+
+                let host = url.host_str().unwrap_or("").to_owned();
+                let mut stats = stats.borrow_mut();
+                let host_stats = stats.entry(host).or_insert_with(HostStats::default);
+
+                host_stats.count += 1;
+                host_stats.total += elapsed;
+                host_stats.max = host_stats.max.max(elapsed);
+
+                extraction
+            })
+        })
+    }
+
+    // Runs other parsing on the same pool, e.g. of feed items.
+    pub fn spawn<F, T>(&self, f: F) -> impl Future<Item=T, Error=()> + 'static
+        where F: FnOnce() -> T + Send + 'static,
+              T: Send + 'static
+    {
+        self.pool.spawn_fn(move || Ok(f()))
+    }
+
+    // Logs hosts that took the most of extraction time since the last report.
+    pub fn report(&self, limit: usize) {
+        let mut stats = self.stats.borrow_mut();
+        let mut hosts = stats.drain().collect::<Vec<_>>();
+
+        hosts.sort_by(|a, b| b.1.total.cmp(&a.1.total));
+
+        for (host, stats) in hosts.into_iter().take(limit) {
+            info!("Extraction on {}: {} pages, {}ms in total, {}ms at most",
+                  host, stats.count, as_millis(stats.total), as_millis(stats.max));
+        }
+    }
+}
+
+fn as_millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + u64::from(duration.subsec_nanos() / 1_000_000)
+}
+
+#[test]
+fn it_collects_host_stats() {
+    let extractor = Extractor::new(1, 1, Duration::from_secs(60));
+    let url = Url::parse("http://example.com/article").unwrap();
+    let document = "<html><body><p>Hello, world!</p></body></html>".to_owned();
+
+    let extraction = extractor.extract(&url, document.clone()).wait().unwrap();
+    assert_eq!(extraction.blocks.len(), 1);
+    assert_eq!(extraction.blocks[0].text, "Hello, world!");
+
+    extractor.extract(&url, document).wait().unwrap();
+
+    {
+        let stats = extractor.stats.borrow();
+        assert_eq!(stats["example.com"].count, 2);
+        assert!(stats["example.com"].max <= stats["example.com"].total);
+    }
+
+    extractor.report(10);
+    assert!(extractor.stats.borrow().is_empty());
+}
@@ -0,0 +1,474 @@
+use std::cmp;
+use std::str;
+
+use rss;
+use rss::extension::ExtensionMap;
+use atom_syndication as atom;
+use serde_json;
+use url::Url;
+
+use meta;
+use quality;
+
+#[derive(Debug, Default)]
+pub struct Channel {
+    pub link: Option<String>,
+    // `xml:base` of the root element, relative links are resolved against it.
+    pub base: Option<String>,
+    pub hints: Hints,
+    pub items: Vec<Item>
+}
+
+// What the publisher says about the update schedule.
+#[derive(Debug, Default, PartialEq)]
+pub struct Hints {
+    // The minimal interval between polls in seconds.
+    pub ttl: Option<u32>,
+    // GMT hours (0-23) and weekdays (0 is Sunday) when the feed isn't updated.
+    pub skip_hours: Vec<u8>,
+    pub skip_days: Vec<u8>
+}
+
+#[derive(Debug, Default)]
+pub struct Item {
+    // The guid, Atom id or JSON Feed id.
+    pub id: Option<String>,
+    pub link: Option<String>,
+    pub title: Option<String>,
+    pub pub_date: Option<String>,
+    pub author: Option<String>,
+    pub description: Option<String>,
+    pub content: Option<String>,
+    // `yandex:full-text`, it's always the whole article.
+    pub full_text: Option<String>
+}
+
+impl Item {
+    // Other content is considered to be the whole article only if it's long enough.
+    pub fn has_full_text(&self, min_length: usize) -> bool {
+        self.full_text.as_ref().map_or(false, |text| !text.trim().is_empty())
+            || self.content.as_ref().map_or(false, |content| quality::text_length(content) >= min_length)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Rss,
+    Atom,
+    Json
+}
+
+pub fn parse(content_type: Option<&str>, body: &[u8]) -> Result<Channel, String> {
+    let format = detect_format(content_type, body).ok_or("Unknown feed format")?;
+
+    let mut channel = match format {
+        Format::Rss => parse_rss(body)?,
+        Format::Atom => parse_atom(body)?,
+        Format::Json => parse_json(body)?
+    };
+
+    channel.base = xml_base(body);
+    Ok(channel)
+}
+
+fn detect_format(content_type: Option<&str>, body: &[u8]) -> Option<Format> {
+    let mime = content_type
+        .and_then(|ct| ct.split(';').next())
+        .map(|mime| mime.trim().to_lowercase());
+
+    match mime.as_ref().map(String::as_str) {
+        Some("application/rss+xml") | Some("application/rdf+xml") => return Some(Format::Rss),
+        Some("application/atom+xml") => return Some(Format::Atom),
+        Some("application/feed+json") | Some("application/json") => return Some(Format::Json),
+        // Generic types like `text/xml` and `text/html` say nothing, so look inside.
+        _ => {}
+    }
+
+    sniff_format(body)
+}
+
+fn sniff_format(body: &[u8]) -> Option<Format> {
+    let head = &body[..body.len().min(4096)];
+    let head = String::from_utf8_lossy(head);
+
+    if head.trim_left_matches('\u{feff}').trim_left().starts_with('{') {
+        return Some(Format::Json);
+    }
+
+    let tag = root_tag(&head)?;
+    let name = tag.split(|c: char| c.is_whitespace() || c == '/').next()?;
+
+    match name {
+        "rss" | "rdf:RDF" => Some(Format::Rss),
+        "feed" => Some(Format::Atom),
+        _ => None
+    }
+}
+
+// The start tag of the root element without angle brackets.
+fn root_tag(head: &str) -> Option<&str> {
+    let mut rest = head.trim_left_matches('\u{feff}').trim_left();
+
+    // Skip the XML declaration, comments, doctypes and processing instructions.
+    while rest.starts_with("<?") || rest.starts_with("<!") {
+        let end = if rest.starts_with("<!--") { rest.find("-->")? + 3 } else { rest.find('>')? + 1 };
+        rest = rest[end..].trim_left();
+    }
+
+    if !rest.starts_with('<') {
+        return None;
+    }
+
+    let end = rest.find('>').unwrap_or_else(|| rest.len());
+    Some(&rest[1..end])
+}
+
+// Items can override the base too, but it's rare enough to be ignored.
+fn xml_base(body: &[u8]) -> Option<String> {
+    let head = &body[..body.len().min(4096)];
+    let head = String::from_utf8_lossy(head);
+
+    let base = meta::attribute(root_tag(&head)?, "xml:base")?.trim();
+
+    if base.is_empty() { None } else { Some(base.to_owned()) }
+}
+
+fn is_web_url(url: &str) -> bool {
+    Url::parse(url.trim()).ok().map_or(false, |url| url.scheme() == "http" || url.scheme() == "https")
+}
+
+fn parse_rss(body: &[u8]) -> Result<Channel, String> {
+    let channel = rss::Channel::read_from(body).map_err(|error| error.to_string())?;
+
+    let hints = rss_hints(&channel);
+
+    let items = channel.items.into_iter().map(|item| {
+        // Some feeds use `dc:date` instead of `pubDate`.
+        let dc_date = item.dublin_core_ext.and_then(|dc| dc.dates.into_iter().next());
+        // Guids are permalinks unless `isPermaLink="false"` is set, but many feeds forget to set it for
+        // ids like "urn:uuid:..." or "12345". So only absolute web urls are taken.
+        let permalink = item.guid.as_ref()
+            .and_then(|guid| if guid.is_permalink { Some(guid.value.clone()) } else { None })
+            .and_then(|value| if is_web_url(&value) { Some(value) } else { None });
+        let full_text = extension_value(&item.extensions, "yandex", "full-text").map(str::to_owned);
+
+        Item {
+            id: item.guid.map(|guid| guid.value),
+            link: item.link.or(permalink),
+            title: item.title,
+            pub_date: item.pub_date.or(dc_date),
+            author: item.author,
+            description: item.description,
+            content: item.content,
+            full_text
+        }
+    }).collect();
+
+    Ok(Channel {
+        link: Some(channel.link),
+        base: None,
+        hints,
+        items
+    })
+}
+
+fn rss_hints(channel: &rss::Channel) -> Hints {
+    let ttl = channel.ttl.as_ref().and_then(|ttl| parse_ttl(ttl));
+
+    let update_period = parse_update_period(
+        extension_value(&channel.extensions, "sy", "updatePeriod"),
+        extension_value(&channel.extensions, "sy", "updateFrequency")
+    );
+
+    Hints {
+        ttl: cmp::max(ttl, update_period),
+        skip_hours: channel.skip_hours.iter()
+            .filter_map(|hour| hour.trim().parse::<u8>().ok())
+            .filter(|&hour| hour < 24)
+            .collect(),
+        skip_days: channel.skip_days.iter()
+            .filter_map(|day| parse_weekday(day))
+            .collect()
+    }
+}
+
+fn extension_value<'a>(extensions: &'a ExtensionMap, prefix: &str, name: &str) -> Option<&'a str> {
+    extensions.get(prefix)
+        .and_then(|extensions| extensions.get(name))
+        .and_then(|extensions| extensions.first())
+        .and_then(|extension| extension.value.as_ref())
+        .map(|value| value.trim())
+}
+
+// Minutes to seconds, huge values are capped by `max_interval` anyway.
+fn parse_ttl(ttl: &str) -> Option<u32> {
+    ttl.trim().parse::<u32>().ok().map(|ttl| ttl.saturating_mul(60))
+}
+
+// See the RSS 1.0 syndication module.
+fn parse_update_period(period: Option<&str>, frequency: Option<&str>) -> Option<u32> {
+    if period.is_none() && frequency.is_none() {
+        return None;
+    }
+
+    let period = match period.unwrap_or("daily") {
+        "hourly" => 3600,
+        "daily" => 24 * 3600,
+        "weekly" => 7 * 24 * 3600,
+        "monthly" => 30 * 24 * 3600,
+        "yearly" => 365 * 24 * 3600,
+        _ => return None
+    };
+
+    let frequency = frequency.map_or(Some(1), |frequency| frequency.parse::<u32>().ok())?;
+
+    if frequency == 0 {
+        return None;
+    }
+
+    Some(period / frequency)
+}
+
+fn parse_weekday(day: &str) -> Option<u8> {
+    let days = ["sunday", "monday", "tuesday", "wednesday", "thursday", "friday", "saturday"];
+    let day = day.trim().to_lowercase();
+
+    days.iter().position(|&name| name == day).map(|idx| idx as u8)
+}
+
+fn parse_atom(body: &[u8]) -> Result<Channel, String> {
+    let feed = atom::Feed::read_from(body).map_err(|error| error.to_string())?;
+
+    let items = feed.entries().iter().map(|entry| Item {
+        id: Some(entry.id().to_owned()),
+        link: alternate_link(entry.links()),
+        title: Some(entry.title().to_owned()),
+        pub_date: Some(entry.published().unwrap_or(entry.updated()).to_owned()),
+        author: entry.authors().first().map(|person| person.name().to_owned()),
+        description: entry.summary().map(str::to_owned),
+        content: entry.content().and_then(|content| content.value()).map(str::to_owned),
+        full_text: None
+    }).collect();
+
+    Ok(Channel {
+        link: alternate_link(feed.links()),
+        base: None,
+        hints: Hints::default(),
+        items
+    })
+}
+
+// Links without `rel` are alternate ones too. Others like `self` point to the feed, not to the site.
+fn alternate_link(links: &[atom::Link]) -> Option<String> {
+    links.iter()
+        .find(|link| link.rel().is_empty() || link.rel() == "alternate")
+        .map(|link| link.href().to_owned())
+}
+
+#[derive(Deserialize)]
+struct JsonFeed {
+    home_page_url: Option<String>,
+    #[serde(default)]
+    items: Vec<JsonItem>
+}
+
+#[derive(Deserialize)]
+struct JsonItem {
+    // Should be a string, but numbers are used too.
+    id: Option<serde_json::Value>,
+    url: Option<String>,
+    external_url: Option<String>,
+    title: Option<String>,
+    summary: Option<String>,
+    content_html: Option<String>,
+    content_text: Option<String>,
+    date_published: Option<String>,
+    date_modified: Option<String>,
+    author: Option<JsonAuthor>
+}
+
+#[derive(Deserialize)]
+struct JsonAuthor {
+    name: Option<String>
+}
+
+fn parse_json(body: &[u8]) -> Result<Channel, String> {
+    let feed = serde_json::from_slice::<JsonFeed>(body).map_err(|error| error.to_string())?;
+
+    let items = feed.items.into_iter().map(|item| Item {
+        id: item.id.and_then(|id| match id {
+            serde_json::Value::String(id) => Some(id),
+            serde_json::Value::Number(id) => Some(id.to_string()),
+            _ => None
+        }),
+        link: item.url.or(item.external_url),
+        title: item.title,
+        pub_date: item.date_published.or(item.date_modified),
+        author: item.author.and_then(|author| author.name),
+        description: item.summary,
+        content: item.content_html.or(item.content_text),
+        full_text: None
+    }).collect();
+
+    Ok(Channel {
+        link: feed.home_page_url,
+        base: None,
+        hints: Hints::default(),
+        items
+    })
+}
+
+#[test]
+fn it_detects_format_by_content_type() {
+    assert_eq!(detect_format(Some("application/rss+xml"), b""), Some(Format::Rss));
+    assert_eq!(detect_format(Some("application/atom+xml; charset=utf-8"), b""), Some(Format::Atom));
+    assert_eq!(detect_format(Some("application/feed+json"), b""), Some(Format::Json));
+    assert_eq!(detect_format(Some("Application/JSON"), b""), Some(Format::Json));
+}
+
+#[test]
+fn it_detects_format_by_root_element() {
+    let rss = b"<?xml version=\"1.0\"?>\n<!-- comment -->\n<rss version=\"2.0\"><channel/></rss>";
+    let rdf = b"<?xml version=\"1.0\"?><rdf:RDF xmlns:rdf=\"...\"></rdf:RDF>";
+    let atom = b"\xef\xbb\xbf<?xml version=\"1.0\"?>\n<feed xmlns=\"http://www.w3.org/2005/Atom\">";
+    let json = b"  {\"version\": \"https://jsonfeed.org/version/1\"}";
+
+    assert_eq!(detect_format(Some("text/xml"), rss), Some(Format::Rss));
+    assert_eq!(detect_format(None, rdf), Some(Format::Rss));
+    assert_eq!(detect_format(Some("text/html"), atom), Some(Format::Atom));
+    assert_eq!(detect_format(None, json), Some(Format::Json));
+    assert_eq!(detect_format(None, b"<html><body/></html>"), None);
+}
+
+#[test]
+fn it_finds_xml_base() {
+    let rss = b"<?xml version=\"1.0\"?>\n<rss version=\"2.0\" xml:base=\"http://example.com/news/\"><channel/></rss>";
+    let atom = b"<feed xmlns=\"http://www.w3.org/2005/Atom\"><entry xml:base=\"/entry/\"/></feed>";
+
+    assert_eq!(xml_base(rss), Some("http://example.com/news/".to_owned()));
+    assert_eq!(xml_base(atom), None);
+    assert_eq!(xml_base(b"{\"xml:base\": \"/\"}"), None);
+}
+
+#[test]
+fn it_finds_full_text() {
+    let body = br#"<?xml version="1.0" encoding="UTF-8"?>
+        <rss version="2.0" xmlns:yandex="http://news.yandex.ru"
+             xmlns:content="http://purl.org/rss/1.0/modules/content/">
+            <channel>
+                <title>Example</title>
+                <link>http://example.com/</link>
+                <description>Example</description>
+                <item>
+                    <title>First</title>
+                    <link>http://example.com/1</link>
+                    <description>Teaser</description>
+                    <yandex:full-text>The whole article.</yandex:full-text>
+                </item>
+                <item>
+                    <title>Second</title>
+                    <link>http://example.com/2</link>
+                    <content:encoded><![CDATA[<p>Not that long</p>]]></content:encoded>
+                </item>
+            </channel>
+        </rss>"#;
+
+    let channel = parse(None, body).unwrap();
+
+    assert_eq!(channel.items[0].full_text, Some("The whole article.".to_owned()));
+    assert!(channel.items[0].has_full_text(1000));
+    assert!(!channel.items[1].has_full_text(1000));
+    assert!(channel.items[1].has_full_text(10));
+}
+
+#[test]
+fn it_takes_permalinks_from_guids() {
+    let body = br#"<?xml version="1.0" encoding="UTF-8"?>
+        <rss version="2.0">
+            <channel>
+                <title>Example</title>
+                <link>http://example.com/</link>
+                <description>Example</description>
+                <item><title>First</title><guid>http://example.com/1</guid></item>
+                <item><title>Second</title><guid>urn:uuid:c2a4e8f0-3b7a-11e7-a919-92ebcb67fe33</guid></item>
+                <item><title>Third</title><guid>12345</guid></item>
+                <item><title>Fourth</title><guid isPermaLink="false">http://example.com/4</guid></item>
+            </channel>
+        </rss>"#;
+
+    let channel = parse(None, body).unwrap();
+    let links = channel.items.iter().map(|item| item.link.as_ref().map(String::as_str)).collect::<Vec<_>>();
+
+    assert_eq!(links, vec![Some("http://example.com/1"), None, None, None]);
+    assert_eq!(channel.items[2].id, Some("12345".to_owned()));
+}
+
+#[test]
+fn it_skips_self_links() {
+    let atom = |links: &str| format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+        <feed xmlns="http://www.w3.org/2005/Atom">
+            <title>Example</title>
+            <id>urn:uuid:60a76c80-d399-11d9-b93c-0003939e0af6</id>
+            <updated>2017-05-17T08:02:12Z</updated>
+            {}
+        </feed>"#, links);
+
+    let body = atom(r#"<link rel="self" href="http://example.com/atom"/><link href="http://example.com/"/>"#);
+    assert_eq!(parse(None, body.as_bytes()).unwrap().link, Some("http://example.com/".to_owned()));
+
+    let body = atom(r#"<link rel="self" href="http://example.com/atom"/>"#);
+    assert_eq!(parse(None, body.as_bytes()).unwrap().link, None);
+}
+
+#[test]
+fn it_parses_json_feed() {
+    let body = br#"{
+        "version": "https://jsonfeed.org/version/1",
+        "home_page_url": "https://example.com/",
+        "items": [{
+            "id": "1",
+            "url": "https://example.com/1",
+            "title": "First",
+            "content_text": "Text",
+            "date_published": "2017-05-17T08:02:12+03:00",
+            "author": {"name": "John"}
+        }]
+    }"#;
+
+    let channel = parse(Some("application/json"), body).unwrap();
+
+    assert_eq!(channel.link, Some("https://example.com/".to_owned()));
+    assert_eq!(channel.items.len(), 1);
+
+    let item = &channel.items[0];
+    assert_eq!(item.id, Some("1".to_owned()));
+    assert_eq!(item.link, Some("https://example.com/1".to_owned()));
+    assert_eq!(item.title, Some("First".to_owned()));
+    assert_eq!(item.content, Some("Text".to_owned()));
+    assert_eq!(item.pub_date, Some("2017-05-17T08:02:12+03:00".to_owned()));
+    assert_eq!(item.author, Some("John".to_owned()));
+}
+
+#[test]
+fn it_parses_ttl() {
+    assert_eq!(parse_ttl(" 60 "), Some(3600));
+    assert_eq!(parse_ttl("4294967295"), Some(u32::max_value()));
+    assert_eq!(parse_ttl("soon"), None);
+}
+
+#[test]
+fn it_parses_update_period() {
+    assert_eq!(parse_update_period(None, None), None);
+    assert_eq!(parse_update_period(Some("hourly"), None), Some(3600));
+    assert_eq!(parse_update_period(None, Some("2")), Some(12 * 3600));
+    assert_eq!(parse_update_period(Some("weekly"), Some("7")), Some(24 * 3600));
+    assert_eq!(parse_update_period(Some("daily"), Some("0")), None);
+    assert_eq!(parse_update_period(Some("sometimes"), None), None);
+}
+
+#[test]
+fn it_parses_weekdays() {
+    assert_eq!(parse_weekday("Sunday"), Some(0));
+    assert_eq!(parse_weekday(" saturday "), Some(6));
+    assert_eq!(parse_weekday("Someday"), None);
+}
@@ -0,0 +1,260 @@
+use std::cmp;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use futures::{future, Future};
+use tokio_core::reactor::{Handle, Timeout};
+use url::Url;
+
+use client::Response;
+use download::{self, FetchError};
+
+struct Host {
+    // Goes below zero when requests are waiting for tokens.
+    tokens: f64,
+    updated: Instant,
+    // The number of failed requests in a row.
+    failures: u32,
+    blocked_until: Option<Instant>
+}
+
+impl Host {
+    fn new(burst: f64, now: Instant) -> Host {
+        Host {
+            tokens: burst,
+            updated: now,
+            failures: 0,
+            blocked_until: None
+        }
+    }
+
+    // Returns how long to wait for the token.
+    fn take(&mut self, now: Instant, rate: f64, burst: f64) -> Duration {
+        let elapsed = as_secs(now - self.updated);
+
+        self.tokens = (self.tokens + elapsed * rate).min(burst) - 1.;
+        self.updated = now;
+
+        if self.tokens >= 0. {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_millis((-self.tokens / rate * 1000.) as u64)
+        }
+    }
+
+    // The circuit stays half-open after the block, so the next failure blocks the host again.
+    fn fail(&mut self, now: Instant, threshold: u32, cooldown: Duration) -> bool {
+        self.failures += 1;
+
+        if self.failures < threshold {
+            return false;
+        }
+
+        self.block(now, cooldown);
+        true
+    }
+
+    fn block(&mut self, now: Instant, duration: Duration) {
+        let until = now + duration;
+        self.blocked_until = Some(self.blocked_until.map_or(until, |blocked| cmp::max(blocked, until)));
+    }
+
+    fn blocked_for(&self, now: Instant) -> Option<Duration> {
+        self.blocked_until.and_then(|until| if until > now { Some(until - now) } else { None })
+    }
+
+    // Forgetting an idle host changes nothing: its bucket is full and it isn't blocked. Failures of
+    // hosts without requests for the cooldown don't matter anymore.
+    fn is_idle(&self, now: Instant, rate: f64, burst: f64, cooldown: Duration) -> bool {
+        let elapsed = now - self.updated;
+
+        self.blocked_for(now).is_none()
+            && self.tokens + as_secs(elapsed) * rate >= burst
+            && (self.failures == 0 || elapsed >= cooldown)
+    }
+}
+
+// Keeps the rate of requests to every host and stops visiting hosts that keep failing.
+#[derive(Clone)]
+pub struct Limiter {
+    handle: Handle,
+    // Tokens per second.
+    rate: f64,
+    burst: f64,
+    failure_threshold: u32,
+    cooldown: Duration,
+    // The longest block requested by `Retry-After`.
+    max_block: Duration,
+    hosts: Rc<RefCell<HashMap<String, Host>>>
+}
+
+impl Limiter {
+    pub fn new(handle: &Handle, rate: f64, burst: u32, failure_threshold: u32, cooldown: Duration,
+               max_block: Duration) -> Limiter
+    {
+        Limiter {
+            handle: handle.clone(),
+            rate,
+            burst: f64::from(burst),
+            failure_threshold,
+            cooldown,
+            max_block,
+            hosts: Rc::new(RefCell::new(HashMap::new()))
+        }
+    }
+
+    // Waits for a token or fails if the host is blocked, before or after waiting.
+    pub fn acquire(&self, url: &Url) -> impl Future<Item=(), Error=FetchError> + 'static {
+        let now = Instant::now();
+        let key = host_of(url);
+        let mut hosts = self.hosts.borrow_mut();
+
+        let burst = self.burst;
+        let host = hosts.entry(key.clone()).or_insert_with(|| Host::new(burst, now));
+
+        if let Some(blocked) = host.blocked_for(now) {
+            return future::Either::A(future::err(blocked_error(blocked)));
+        }
+
+        let wait = host.take(now, self.rate, self.burst);
+        let timeout = Timeout::new(wait, &self.handle).unwrap();
+        let hosts = self.hosts.clone();
+
+        // The circuit can break while requests wait for their tokens, they must not hit the host then.
+        future::Either::B(timeout.then(move |_| {
+            let blocked = hosts.borrow().get(&key).and_then(|host| host.blocked_for(Instant::now()));
+
+            match blocked {
+                Some(blocked) => Err(blocked_error(blocked)),
+                None => Ok(())
+            }
+        }))
+    }
+
+    // Forgets hosts that are no different from new ones.
+    pub fn evict_idle(&self) {
+        let now = Instant::now();
+        let (rate, burst, cooldown) = (self.rate, self.burst, self.cooldown);
+
+        self.hosts.borrow_mut().retain(|_, host| !host.is_idle(now, rate, burst, cooldown));
+    }
+
+    pub fn record(&self, url: &Url, result: &Result<Response, FetchError>) {
+        let now = Instant::now();
+        let mut hosts = self.hosts.borrow_mut();
+
+        let burst = self.burst;
+        let host = hosts.entry(host_of(url)).or_insert_with(|| Host::new(burst, now));
+
+        let failed = match *result {
+            Ok(ref response) => match response.status_code() {
+                429 | 503 => {
+                    if let Some(secs) = download::requested_delay(response) {
+                        info!("{} asks to wait for {}s", host_of(url), secs);
+                        host.block(now, cmp::min(Duration::from_secs(u64::from(secs)), self.max_block));
+                    }
+
+                    true
+                },
+                code => code >= 500
+            },
+            Err(FetchError::Network(_)) | Err(FetchError::Timeout) => true,
+            Err(_) => false
+        };
+
+        if !failed {
+            host.failures = 0;
+            return;
+        }
+
+        if host.fail(now, self.failure_threshold, self.cooldown) {
+            warn!("{} is failing, stop visiting it for {}s", host_of(url), self.cooldown.as_secs());
+        }
+    }
+}
+
+fn host_of(url: &Url) -> String {
+    url.host_str().unwrap_or("").to_owned()
+}
+
+fn blocked_error(blocked: Duration) -> FetchError {
+    FetchError::Blocked(blocked.as_secs() as u32 + 1)
+}
+
+fn as_secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) * 1e-9
+}
+
+#[test]
+fn it_limits_rate() {
+    let now = Instant::now();
+    let mut host = Host::new(2., now);
+
+    assert_eq!(host.take(now, 0.5, 2.), Duration::from_secs(0));
+    assert_eq!(host.take(now, 0.5, 2.), Duration::from_secs(0));
+    assert_eq!(host.take(now, 0.5, 2.), Duration::from_secs(2));
+    assert_eq!(host.take(now, 0.5, 2.), Duration::from_secs(4));
+
+    // The debt is paid off, but the bucket doesn't overflow.
+    let later = now + Duration::from_secs(100);
+    assert_eq!(host.take(later, 0.5, 2.), Duration::from_secs(0));
+    assert_eq!(host.take(later, 0.5, 2.), Duration::from_secs(0));
+    assert_eq!(host.take(later, 0.5, 2.), Duration::from_secs(2));
+}
+
+#[test]
+fn it_breaks_circuit() {
+    let now = Instant::now();
+    let cooldown = Duration::from_secs(60);
+    let mut host = Host::new(1., now);
+
+    assert!(!host.fail(now, 3, cooldown));
+    assert!(!host.fail(now, 3, cooldown));
+    assert_eq!(host.blocked_for(now), None);
+
+    assert!(host.fail(now, 3, cooldown));
+    assert_eq!(host.blocked_for(now), Some(cooldown));
+    assert_eq!(host.blocked_for(now + cooldown), None);
+
+    // Half-open: a single failure is enough.
+    let later = now + cooldown;
+    assert!(host.fail(later, 3, cooldown));
+    assert_eq!(host.blocked_for(later), Some(cooldown));
+}
+
+#[test]
+fn it_finds_idle_hosts() {
+    let now = Instant::now();
+    let cooldown = Duration::from_secs(60);
+    let mut host = Host::new(2., now);
+
+    assert!(host.is_idle(now, 0.5, 2., cooldown));
+
+    host.take(now, 0.5, 2.);
+    assert!(!host.is_idle(now, 0.5, 2., cooldown));
+    assert!(host.is_idle(now + Duration::from_secs(2), 0.5, 2., cooldown));
+
+    host.fail(now, 1, cooldown);
+    assert!(!host.is_idle(now + Duration::from_secs(30), 0.5, 2., cooldown));
+    assert!(host.is_idle(now + cooldown, 0.5, 2., cooldown));
+}
+
+#[test]
+fn it_rejects_waiting_requests_of_broken_circuits() {
+    use tokio_core::reactor::Core;
+
+    let mut core = Core::new().unwrap();
+    let cooldown = Duration::from_secs(60);
+    let limiter = Limiter::new(&core.handle(), 10., 1, 1, cooldown, cooldown);
+    let url = Url::parse("http://example.com/").unwrap();
+
+    let first = limiter.acquire(&url);
+    let second = limiter.acquire(&url);
+
+    core.run(first).unwrap();
+    limiter.record(&url, &Err(FetchError::Timeout));
+
+    assert!(match core.run(second) { Err(FetchError::Blocked(_)) => true, _ => false });
+}
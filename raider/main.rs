@@ -4,55 +4,108 @@ extern crate common;
 #[macro_use]
 extern crate log;
 extern crate futures;
+extern crate futures_cpupool;
 extern crate tokio_core;
-extern crate tokio_request;
+extern crate tokio_curl;
+extern crate curl;
 extern crate rss;
+extern crate atom_syndication;
+extern crate chrono;
 extern crate time;
 extern crate mailparse;
 extern crate readability;
+extern crate kuchiki;
+extern crate encoding;
+#[macro_use]
+extern crate serde_derive;
 extern crate serde_json;
 extern crate kafka;
 extern crate url;
 
 use std::cmp;
+use std::mem;
 use std::thread;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::time::Duration;
 
 use time::Timespec;
-use tokio_core::reactor::{Core, Handle};
+use tokio_core::reactor::{Core, Interval};
 use futures::future;
-use futures::{Future, Stream};
-use rss::Channel;
+use futures::{stream, Future, Stream};
 use url::Url;
-use readability::Readability;
 use kafka::consumer::{Consumer, FetchOffset};
 use kafka::producer::{Producer, Record, Partitioner};
 
 use common::logger;
+use common::config::{self, Config, RaiderConfig};
+use common::bloom;
 use common::key::Key;
-use common::messages::{Feed, Entry};
+use common::messages::{Feed, Entry, ContentSource};
 use scheduler::Scheduler;
+use feed::Channel;
+use dedup::Deduplicator;
+use robots::{Robots, Permit};
+use download::{Validators, FetchError};
+use client::Client;
+use limiter::Limiter;
+use retry::{Retry, RetryQueue};
+use semaphore::Semaphore;
+use extract::{Extractor, Extraction};
 
 mod scheduler;
+mod client;
+mod limiter;
+mod retry;
 mod download;
+mod feed;
+mod dedup;
+mod robots;
+mod semaphore;
+mod extract;
+mod charset;
+mod meta;
+mod date;
+mod quality;
+mod blocks;
 
-const KAFKA_URL: &str = "localhost:9092";
-const MIN_INTERVAL: u32 = 3600;
-const MAX_INTERVAL: u32 = 24 * 3600;
-const PROMPTNESS: f32 = 0.5;
+// The publisher's hint (TTL and so on) is a lower bound, but it cannot exceed `max_interval`.
+fn estimate_interval(config: &RaiderConfig, hint: Option<u32>, prev: u32, total: u32, new: u32) -> u32 {
+    let max_interval = config.max_interval;
+    let min_interval = hint.map_or(config.min_interval, |hint| {
+        cmp::min(cmp::max(hint, config.min_interval), max_interval)
+    });
 
-fn estimate_interval(prev: u32, total: u32, new: u32) -> u32 {
     if total == 0 {
-        return cmp::min(prev + MIN_INTERVAL, MAX_INTERVAL);
+        return cmp::min(prev + min_interval, max_interval);
     }
 
     let staleness = (total - new) as f32 / total as f32;
-    let delta = staleness - PROMPTNESS;
-    let estimated = prev as f32 * (1. + delta / PROMPTNESS);
+    let delta = staleness - config.promptness;
+    let estimated = prev as f32 * (1. + delta / config.promptness);
     let trust = (total as f32 / 30.).min(1.);
 
     let next = prev as f32 * (1. - trust) + estimated * trust;
 
-    cmp::max(MIN_INTERVAL, cmp::min(next as u32, MAX_INTERVAL))
+    cmp::max(min_interval, cmp::min(next as u32, max_interval))
+}
+
+// Failed visits don't touch the estimated interval, only the delay of the next visit.
+fn retry_delay(config: &RaiderConfig, failures: u32, error: &FetchError) -> u32 {
+    let backoff = |base: u32| cmp::min(base.saturating_mul(1u32 << cmp::min(failures, 16)), config.max_interval);
+
+    match *error {
+        // The feed is most likely removed.
+        FetchError::Status { code: 404, .. } | FetchError::Status { code: 410, .. } => config.max_interval,
+        FetchError::Status { retry_after: Some(secs), .. } | FetchError::Blocked(secs) =>
+            cmp::min(secs, config.max_interval),
+        FetchError::Status { code, .. } if code < 500 => backoff(config.min_interval),
+        // Temporary troubles, so try again soon.
+        FetchError::Status { .. } | FetchError::Network(_) | FetchError::Timeout => backoff(config.retry_interval),
+        // Broken feeds are rarely fixed quickly.
+        FetchError::Decode(_) | FetchError::Parse(_) | FetchError::Redirect(_) | FetchError::TooLarge(_) =>
+            backoff(config.min_interval)
+    }
 }
 
 fn purify_text(string: String) -> Option<String> {
@@ -69,19 +122,38 @@ fn purify_text(string: String) -> Option<String> {
     }
 }
 
-fn parse_rfc822_date(date: &str) -> Option<Timespec> {
-    match mailparse::dateparse(date) {
-        Ok(date) => Some(Timespec::new(date, 0)),
-        Err(error) => {
-            warn!("Cannot parse \"{}\" as date: {}", date, error);
-            None
-        }
+// Returns the time and the offset if the date has a zone.
+fn parse_date(date: &str, default_offset: i32) -> Option<(Timespec, Option<i32>)> {
+    let parsed = date::parse(date, default_offset);
+
+    if parsed.is_none() {
+        warn!("Cannot parse \"{}\" as date", date);
     }
+
+    parsed
+}
+
+// Dates without zones are in the feed's zone if it's known.
+fn default_offset(config: &RaiderConfig, feed: &Feed) -> i32 {
+    feed.timezone.unwrap_or(config.default_utc_offset)
 }
 
-fn parse_url(url: &str) -> Option<Url> {
-    match Url::parse(url.trim()) {
-        Ok(url) => Some(url),
+// Relative links are resolved against `base`, absolute ones ignore it.
+fn parse_url(base: &Url, url: &str) -> Option<Url> {
+    let url = url.trim();
+
+    // An empty reference is the base itself.
+    if url.is_empty() {
+        return None;
+    }
+
+    match base.join(url) {
+        // Other schemes like "urn:" or "mailto:" can't be fetched.
+        Ok(url) if url.scheme() == "http" || url.scheme() == "https" => Some(url),
+        Ok(url) => {
+            warn!("Skipping {} with unsupported scheme", url);
+            None
+        },
         Err(_) => {
             warn!("Cannot parse \"{}\" as url", url);
             None
@@ -89,48 +161,133 @@ fn parse_url(url: &str) -> Option<Url> {
     }
 }
 
-fn fetch_entries(handle: &Handle, mut feed: Feed)
-    -> impl Future<Item=(Feed, Vec<Entry>), Error=()>
+struct Visit {
+    feed: Feed,
+    entries: Vec<Entry>,
+    // Entries with pages that cannot be fetched now, but can be later. Undated ones have the default
+    // offset for the page's date.
+    failed: Vec<(Entry, Option<i32>)>,
+    // Entries without dates in the feed, their dates are looked for in pages.
+    undated: HashSet<Url>,
+    // Entries with the whole article in the feed, their pages aren't fetched unless they're undated.
+    full_text: HashSet<Url>,
+    // The previous url if the feed is moved permanently.
+    moved_from: Option<Url>
+}
+
+impl Visit {
+    fn new(feed: Feed) -> Visit {
+        Visit {
+            feed,
+            entries: Vec::new(),
+            failed: Vec::new(),
+            undated: HashSet::new(),
+            full_text: HashSet::new(),
+            moved_from: None
+        }
+    }
+}
+
+fn fetch_entries(client: &Client, config: &RaiderConfig, extractor: &Extractor, mut feed: Feed)
+    -> impl Future<Item=Visit, Error=()>
 {
     info!("Fetching {} feed...", feed.url);
 
-    download::channel(handle, &feed.url).then(|channel| {
-        Ok(match channel {
-            Ok(channel) => disassemble_channel(feed, channel),
+    let config = config.clone();
+
+    let validators = Validators {
+        etag: feed.etag.clone(),
+        last_modified: feed.last_modified.clone()
+    };
+
+    let client = client.clone();
+    let extractor = extractor.clone();
+    let url = feed.url.clone();
+    let max_size = config.max_feed_size;
+
+    let response = client.ready(&feed.url)
+        .and_then(move |_| download::channel(&client, &url, &validators, max_size));
+
+    response.then(move |response| {
+        let response = match response {
+            Ok(response) => response,
             Err(error) => {
                 warn!("Fetching {} is failed: {}", feed.url, error);
 
-                feed.interval = estimate_interval(feed.interval, 0, 0);
+                feed.retry = Some(retry_delay(&config, feed.failures, &error));
+                feed.failures += 1;
 
-                (feed, Vec::new())
+                return future::Either::A(future::ok(Visit::new(feed)));
             }
-        })
-    })
-}
+        };
+
+        feed.failures = 0;
+        feed.retry = None;
 
-fn disassemble_channel(mut feed: Feed, channel: Channel) -> (Feed, Vec<Entry>) {
-    let (mut total_count, mut new_count) = (0, 0);
+        let moved_from = response.moved_to.map(|url| {
+            info!("{} is moved permanently to {}", feed.url, url);
+            mem::replace(&mut feed.url, url)
+        });
 
-    feed.source = parse_url(&channel.link).unwrap_or(feed.source);
+        let visit = match response.channel {
+            Some(channel) => {
+                feed.etag = response.validators.etag;
+                feed.last_modified = response.validators.last_modified;
 
-    let entries = channel.items.into_iter().filter_map(|item| {
-        let url = match item.link.and_then(|url| parse_url(&url)) {
-            Some(url) => url,
+                // Items are parsed as HTML, so it's done off the reactor.
+                future::Either::A(extractor.spawn(move || disassemble_channel(&config, feed, channel)))
+            },
             None => {
-                warn!("Got item without link from {}", feed.url);
-                return None;
+                debug!("{} is not modified", feed.url);
+
+                feed.interval = estimate_interval(&config, feed.ttl, feed.interval, feed.size, 0);
+
+                future::Either::B(future::ok(Visit::new(feed)))
             }
         };
 
-        let published = match item.pub_date.and_then(|date| parse_rfc822_date(&date)) {
-            Some(published) => published,
+        future::Either::B(visit.map(move |mut visit| {
+            visit.moved_from = moved_from;
+            visit
+        }))
+    })
+}
+
+// Items are new if their ids aren't seen in the previous versions of the feed. Items without
+// dates get the current time.
+fn disassemble_channel(config: &RaiderConfig, mut feed: Feed, channel: Channel) -> Visit {
+    let (mut total_count, mut new_count, mut full_text_count) = (0, 0, 0);
+    let mut augmented = feed.augmented;
+    let mut timezone = feed.timezone;
+    let offset = default_offset(config, &feed);
+
+    let now = time::get_time();
+    let previous = mem::replace(&mut feed.seen, Vec::new());
+    // Feeds visited before ids were tracked have only the latest date.
+    let legacy = previous.is_empty() && feed.augmented > Timespec::new(0, 0);
+    let seen = previous.iter().cloned().collect::<HashSet<_>>();
+    let (mut current, mut hashes) = (Vec::new(), HashSet::new());
+    let (mut undated, mut full_text) = (HashSet::new(), HashSet::new());
+
+    // `xml:base` applies to the channel link too, otherwise links are relative to the feed.
+    let xml_base = channel.base.and_then(|base| parse_url(&feed.url, &base));
+
+    if let Some(source) = channel.link.and_then(|link| parse_url(xml_base.as_ref().unwrap_or(&feed.url), &link)) {
+        feed.source = source;
+    }
+
+    let base = xml_base.unwrap_or_else(|| feed.source.clone());
+
+    let entries = channel.items.into_iter().filter_map(|mut item| {
+        let url = match item.link.take().and_then(|url| parse_url(&base, &url)) {
+            Some(url) => url,
             None => {
-                warn!("Got item {} without published date from {}", url, feed.url);
+                warn!("Got item without link from {}", feed.url);
                 return None;
             }
         };
 
-        let title = match item.title.and_then(purify_text) {
+        let title = match item.title.take().and_then(purify_text) {
             Some(title) => title,
             None => {
                 warn!("Got item {} without title from {}", url, feed.url);
@@ -138,69 +295,333 @@ fn disassemble_channel(mut feed: Feed, channel: Channel) -> (Feed, Vec<Entry>) {
             }
         };
 
+        // Links are used if there are no guids. Keys aren't, they ignore queries.
+        let id = item.id.take().and_then(purify_text).unwrap_or_else(|| url.as_str().to_owned());
+        let hash = bloom::hash(id.as_bytes());
+
+        if !hashes.insert(hash) {
+            debug!("  Got item {} twice from {}", id, feed.url);
+            return None;
+        }
+
         total_count += 1;
 
-        if published <= feed.augmented {
+        let date = item.pub_date.take().and_then(|date| parse_date(&date, offset)).map(|(published, zone)| {
+            if zone.is_some() {
+                timezone = zone;
+            }
+
+            // A single date from the future would break the order of entries.
+            if published > now {
+                debug!("  Got item {} from the future from {}", url, feed.url);
+            }
+
+            cmp::min(published, now)
+        });
+
+        let is_seen = seen.contains(&hash) || (legacy && date.map_or(false, |published| published <= feed.augmented));
+        current.push(hash);
+
+        if is_seen {
             return None;
         }
 
         new_count += 1;
 
+        let published = match date {
+            Some(published) => {
+                augmented = cmp::max(augmented, published);
+                published
+            },
+            None => {
+                debug!("  Got item {} without published date from {}", url, feed.url);
+                undated.insert(url.clone());
+                now
+            }
+        };
+
+        // Only new items are parsed, seen ones are dropped anyway.
+        if item.has_full_text(config.min_full_text_length) {
+            full_text_count += 1;
+            full_text.insert(url.clone());
+        }
+
         let description = item.description.and_then(purify_text);
-        let content = item.content.and_then(purify_text);
+        let content = item.full_text.and_then(purify_text).or(item.content.and_then(purify_text));
+        let content = content.or(description).unwrap_or_else(String::new);
 
         Some(Entry {
+            blocks: blocks::parse(&content, &url),
             url,
             title,
             published,
             source: feed.source.clone(),
             author: item.author.and_then(purify_text),
-            content: content.or(description).unwrap_or_else(String::new)
+            content,
+            content_source: ContentSource::Feed
         })
     }).collect();
 
-    // TODO: use `channel.ttl` as some assumption about `feed.interval`.
-    feed.interval = estimate_interval(feed.interval, total_count, new_count);
+    feed.augmented = augmented;
+    feed.timezone = timezone;
+    feed.seen = remember_seen(current, previous, config.max_seen_items);
+
+    feed.ttl = channel.hints.ttl;
+    feed.skip_hours = channel.hints.skip_hours;
+    feed.skip_days = channel.hints.skip_days;
 
-    (feed, entries)
+    feed.interval = estimate_interval(config, feed.ttl, feed.interval, total_count, new_count);
+    feed.size = total_count;
+
+    if new_count > 0 {
+        feed.full_text = full_text_count;
+    }
+
+    Visit { entries, undated, full_text, ..Visit::new(feed) }
 }
 
-fn fetch_documents(handle: &Handle, feed: Feed, entries: Vec<Entry>)
-    -> impl Future<Item=(Feed, Vec<Entry>), Error=()> + 'static
+// Hashes take up to 21 bytes in JSON, so this many of them leave room for other fields of the feed in
+// 64 KiB messages of the feeds topic (see `scripts/create_feeds_topic.sh`).
+const MAX_SEEN_ITEMS: usize = 2000;
+
+// Items of the current version are kept, the rest are remembered while there is room. It helps when
+// items disappear from the feed for a while. Huge feeds are cut, the dedup filter still catches the rest.
+fn remember_seen(mut current: Vec<u64>, previous: Vec<u64>, limit: usize) -> Vec<u64> {
+    current.truncate(MAX_SEEN_ITEMS);
+
+    let room = cmp::min(limit, MAX_SEEN_ITEMS).saturating_sub(current.len());
+    let kept = {
+        let hashes = current.iter().collect::<HashSet<_>>();
+        previous.into_iter().filter(|hash| !hashes.contains(hash)).take(room).collect::<Vec<_>>()
+    };
+
+    current.extend(kept);
+    current
+}
+
+// Duplicates are still seen by the feed, so they aren't new next time.
+fn drop_duplicates(dedup: &mut Deduplicator, entries: Vec<Entry>) -> Vec<Entry> {
+    entries.into_iter().filter(|entry| {
+        if dedup.insert(&entry.url) {
+            return true;
+        }
+
+        debug!("  Skipping {} entry, it's already seen", entry.url);
+        false
+    }).collect()
+}
+
+fn fetch_documents(client: &Client, config: &RaiderConfig, robots: &Robots, downloads: &Semaphore,
+                   extractor: &Extractor, mut visit: Visit)
+    -> impl Future<Item=Visit, Error=()> + 'static
 {
-    let fetchers = entries.into_iter().map(|mut entry| {
-        debug!("  Fetching {} entry...", entry.url);
+    let entries = mem::replace(&mut visit.entries, Vec::new());
+    let undated = mem::replace(&mut visit.undated, HashSet::new());
+    let full_text = mem::replace(&mut visit.full_text, HashSet::new());
+    let offset = default_offset(config, &visit.feed);
 
-        let download = download::document(handle, &entry.url);
+    let fetchers = entries.into_iter().map(|entry| {
+        let undated = if undated.contains(&entry.url) { Some(offset) } else { None };
 
-        download.then(|result| {
-            let document = match result {
-                Ok(document) => document,
-                Err(error) => {
-                    warn!("Fetching {} is failed: {}", entry.url, error);
-                    return Ok(None);
-                }
-            };
+        // Pages of undated entries are still fetched for their dates.
+        if full_text.contains(&entry.url) && undated.is_none() {
+            debug!("  {} has full text in the feed, skipping the page", entry.url);
+            return future::Either::B(future::ok((Ok(entry), undated)));
+        }
 
-            // TODO: should we use a thread pool here?
-            let content = Readability::new().parse(&document).text_contents();
+        let client = client.clone();
+        let downloads = downloads.clone();
+        let extractor = extractor.clone();
+        let max_size = config.max_document_size;
 
-            // TODO: leave original `content` in some situations.
-            entry.content = content;
-            Ok(Some(entry))
-        })
+        future::Either::A(robots.permit(&entry.url).and_then(move |permit| {
+            if permit != Permit::Allowed {
+                return future::Either::B(future::ok((unpermitted(permit, entry), undated)));
+            }
+
+            let fetch = fetch_document(&client, &downloads, &extractor, max_size, undated, entry);
+
+            // Only transient failures are parked.
+            future::Either::A(fetch.map(move |result| {
+                let result = result.or_else(|(entry, error)| if error.is_transient() { Err(entry) } else { Ok(entry) });
+                (result, undated)
+            }))
+        }))
     }).collect::<Vec<_>>();
 
-    future::join_all(fetchers).map(|entries| {
-        let entries = entries.into_iter().filter_map(|entry| entry).collect();
-        (feed, entries)
+    future::join_all(fetchers).map(|results| {
+        for (result, undated) in results {
+            match result {
+                Ok(entry) => visit.entries.push(entry),
+                Err(entry) => visit.failed.push((entry, undated))
+            }
+        }
+
+        visit
+    })
+}
+
+// Disallowed pages are never fetched, so the feed content is sent. Unreachable robots.txt is likely to be
+// fetched later, e.g. when the host is back, so the entry is parked like after transient failures.
+fn unpermitted(permit: Permit, entry: Entry) -> Result<Entry, Entry> {
+    if permit == Permit::Unknown {
+        debug!("  Rules for {} are unknown, postponing the entry", entry.url);
+        Err(entry)
+    } else {
+        debug!("  Fetching {} is disallowed, leaving the feed content", entry.url);
+        Ok(entry)
+    }
+}
+
+// Resolves to the original entry and the error if the page cannot be fetched.
+fn fetch_document(client: &Client, downloads: &Semaphore, extractor: &Extractor, max_size: usize,
+                  undated: Option<i32>, entry: Entry)
+    -> impl Future<Item=Result<Entry, (Entry, FetchError)>, Error=()> + 'static
+{
+    let client = client.clone();
+    let downloads = downloads.clone();
+    let extractor = extractor.clone();
+
+    // Waiting for the crawl delay and the host's rate doesn't occupy the download slot.
+    client.ready(&entry.url).then(move |ready| {
+        if let Err(error) = ready {
+            warn!("Fetching {} is failed: {}", entry.url, error);
+            return future::Either::A(future::ok(Err((entry, error))));
+        }
+
+        // The slot is held during the extraction too, so a busy pool slows down downloads.
+        future::Either::B(downloads.acquire().and_then(move |permit| {
+            extract_document(&client, &extractor, max_size, undated, entry).then(move |result| {
+                drop(permit);
+                result
+            })
+        }))
+    })
+}
+
+// The publication date of undated entries is looked for in the page, the first-seen time stays otherwise.
+// `undated` is the default offset for the page's date.
+fn extract_document(client: &Client, extractor: &Extractor, max_size: usize, undated: Option<i32>,
+                    mut entry: Entry) -> impl Future<Item=Result<Entry, (Entry, FetchError)>, Error=()> + 'static
+{
+    debug!("  Fetching {} entry...", entry.url);
+
+    let download = download::document(client, &entry.url, max_size);
+    let extractor = extractor.clone();
+
+    download.then(move |result| {
+        let document = match result {
+            Ok(document) => document,
+            Err(error) => {
+                warn!("Fetching {} is failed: {}", entry.url, error);
+                return future::Either::A(future::ok(Err((entry, error))));
+            }
+        };
+
+        if let Some(offset) = undated {
+            if let Some((published, _)) = meta::published_time(&document).and_then(|date| parse_date(&date, offset)) {
+                debug!("  Found published date of {} in the page", entry.url);
+                entry.published = cmp::min(published, entry.published);
+            }
+        }
+
+        let spawner = extractor.clone();
+
+        // The feed content is parsed too, so it's chosen off the reactor.
+        future::Either::B(extractor.extract(&entry.url, document).and_then(move |extraction| {
+            spawner.spawn(move || {
+                choose_content(&mut entry, extraction);
+                entry
+            }).map(Ok)
+        }))
+    })
+}
+
+// The extracted content replaces the feed one unless it's worse, e.g. empty or navigation only.
+fn choose_content(entry: &mut Entry, extraction: Extraction) {
+    let (feed_text, feed_linked) = quality::html_text(&entry.content);
+
+    let feed_score = quality::score(&entry.title, &feed_text, feed_linked);
+    let page_score = quality::score(&entry.title, &extraction.text, extraction.linked);
+
+    if page_score < feed_score {
+        debug!("  Leaving the feed content of {} ({:.2} against {:.2})", entry.url, feed_score, page_score);
+        return;
+    }
+
+    entry.content = extraction.text;
+    entry.blocks = extraction.blocks;
+    entry.content_source = ContentSource::Page;
+}
+
+// Resolves to the entry to send or to the retry to park again. Only transient errors are retried.
+fn retry_document(client: &Client, config: &RaiderConfig, robots: &Robots, downloads: &Semaphore,
+                  extractor: &Extractor, retry: Retry) -> impl Future<Item=Result<Entry, Retry>, Error=()> + 'static
+{
+    let Retry { entry, attempts, undated, .. } = retry;
+    let client = client.clone();
+    let config = config.clone();
+    let downloads = downloads.clone();
+    let extractor = extractor.clone();
+
+    debug!("  Retrying {} entry after {} attempts...", entry.url, attempts);
+
+    robots.permit(&entry.url).and_then(move |permit| {
+        if permit != Permit::Allowed {
+            let result = unpermitted(permit, entry).or_else(|entry| park(&config, entry, undated, attempts + 1));
+            return future::Either::B(future::ok(result));
+        }
+
+        let max_size = config.max_document_size;
+
+        let fetch = fetch_document(&client, &downloads, &extractor, max_size, undated, entry);
+
+        future::Either::A(fetch.map(move |result| {
+            result.or_else(|(entry, error)| {
+                if error.is_transient() {
+                    park(&config, entry, undated, attempts + 1)
+                } else {
+                    Ok(entry)
+                }
+            })
+        }))
     })
 }
 
-fn scheduling(scheduler: Scheduler<Feed>) {
-    let mut consumer = Consumer::from_hosts(vec![KAFKA_URL.to_owned()])
+// Entries that have run out of attempts are sent with the feed content.
+fn park(config: &RaiderConfig, entry: Entry, undated: Option<i32>, attempts: u32) -> Result<Entry, Retry> {
+    if attempts > config.max_document_retries {
+        warn!("Giving up fetching {} after {} attempts, leaving the feed content", entry.url, attempts);
+        return Ok(entry);
+    }
+
+    let now = time::get_time().sec;
+
+    Err(Retry::new(entry, undated, attempts, now, config.retry_interval, config.max_interval))
+}
+
+// Moves the poll out of hours and days when the publisher doesn't update the feed.
+fn postpone(feed: &Feed, at: i64) -> i64 {
+    let mut next = at;
+
+    // Try every hour of the week, unless everything is skipped.
+    for _ in 0..7 * 24 {
+        let tm = time::at_utc(Timespec::new(next, 0));
+
+        if !feed.skip_hours.contains(&(tm.tm_hour as u8)) && !feed.skip_days.contains(&(tm.tm_wday as u8)) {
+            return next;
+        }
+
+        next = next - next % 3600 + 3600;
+    }
+
+    at
+}
+
+fn scheduling(config: &Config, scheduler: Scheduler<Feed>) {
+    let mut consumer = Consumer::from_hosts(config.kafka.hosts())
         .with_fallback_offset(FetchOffset::Earliest)
-        .with_topic("feeds".to_owned())
+        .with_topic(config.topics.feeds.clone())
         .create().unwrap();
 
     info!("Start scheduling...");
@@ -208,16 +629,25 @@ fn scheduling(scheduler: Scheduler<Feed>) {
     loop {
         for message_set in consumer.poll().unwrap().iter() {
             for message in message_set.messages() {
+                // The feed is moved or removed.
+                if message.value.is_empty() {
+                    continue;
+                }
+
                 let feed = match serde_json::from_slice::<Feed>(message.value) {
                     Ok(feed) => feed,
                     Err(error) => {
-                        error!("Invalid message on \"feeds\" topic: {}", error);
+                        error!("Invalid message on \"{}\" topic: {}", config.topics.feeds, error);
                         continue;
                     }
                 };
 
-                info!("Scheduling {} after {}s...", feed.url, feed.interval);
-                scheduler.schedule(feed.interval as u64 * 1000, feed);
+                let now = time::get_time().sec;
+                let interval = feed.retry.unwrap_or(feed.interval);
+                let delay = postpone(&feed, now + interval as i64) - now;
+
+                info!("Scheduling {} after {}s...", feed.url, delay);
+                scheduler.schedule(delay as u64 * 1000, feed);
             }
 
             consumer.consume_messageset(message_set).unwrap();
@@ -227,79 +657,602 @@ fn scheduling(scheduler: Scheduler<Feed>) {
     }
 }
 
-fn send_feed<P>(producer: &mut Producer<P>, feed: Feed)
+fn send_feed<P>(producer: &mut Producer<P>, topic: &str, feed: Feed)
     where P: Partitioner
 {
     let key: String = Key::from(feed.url.clone()).into();
     let value = serde_json::to_vec(&feed).unwrap();
 
-    producer.send(&Record::from_key_value("feeds", key, value)).unwrap();
+    producer.send(&Record::from_key_value(topic, key, value)).unwrap();
 }
 
-fn send_entries<P>(producer: &mut Producer<P>, entries: Vec<Entry>)
+// Compaction removes records with null values. kafka-rust 0.6 encodes empty values as null ones
+// (`MessageProduceRequest::new` in `protocol/produce.rs` maps them to `None`), so `()` is a tombstone.
+fn send_tombstone<P>(producer: &mut Producer<P>, topic: &str, key: Key)
+    where P: Partitioner
+{
+    let key: String = key.into();
+
+    producer.send(&Record::from_key_value(topic, key, ())).unwrap();
+}
+
+fn send_entries<P>(producer: &mut Producer<P>, topic: &str, entries: Vec<Entry>)
     where P: Partitioner
 {
     let records = entries.into_iter()
-        .map(|entry| Record::from_value("entries", serde_json::to_vec(&entry).unwrap()))
+        .filter_map(serialize_entry)
+        .map(|value| Record::from_value(topic, value))
         .collect::<Vec<_>>();
 
     producer.send_all(&records).unwrap();
 }
 
-fn fetching<S>(stream: S)
+// Messages of the entries topic are 1 MiB at most (see `scripts/create_entries_topic.sh`), this leaves
+// room for the rest of the record.
+const MAX_ENTRY_SIZE: usize = 1000 * 1024;
+
+// Blocks repeat the text of the content, so they're dropped first if the entry doesn't fit into a message.
+// Entries that don't fit even without them are skipped, the broker would reject them anyway.
+fn serialize_entry(mut entry: Entry) -> Option<Vec<u8>> {
+    let value = serde_json::to_vec(&entry).unwrap();
+
+    if value.len() <= MAX_ENTRY_SIZE {
+        return Some(value);
+    }
+
+    if entry.blocks.is_empty() {
+        warn!("Skipping {} entry, it takes {} bytes", entry.url, value.len());
+        return None;
+    }
+
+    debug!("  Dropping blocks of {} entry, it takes {} bytes", entry.url, value.len());
+    entry.blocks.clear();
+
+    serialize_entry(entry)
+}
+
+// How often the retry queue is checked and saved, in seconds.
+const RETRY_CHECK_PERIOD: u64 = 10;
+// How often state of hosts without recent requests is dropped.
+const HOST_EVICTION_PERIOD: u64 = 10 * 60;
+
+fn fetching<S>(config: &Config, stream: S)
     where S: Stream<Item=Feed, Error=()>
 {
-    let mut producer = Producer::from_hosts(vec![KAFKA_URL.to_owned()]).create().unwrap();
+    let producer = Producer::from_hosts(config.kafka.hosts()).create().unwrap();
+    let producer = RefCell::new(producer);
+
+    let raider = &config.raider;
+    let dedup = Deduplicator::open(&raider.dedup_file, raider.dedup_capacity, raider.dedup_fp_rate);
+    let dedup = RefCell::new(dedup.unwrap());
+    let mut saved_at = time::get_time();
+
+    let queue = RefCell::new(RetryQueue::open(&raider.retry_file).unwrap());
+    info!("{} entries are waiting for retries", queue.borrow().len());
 
     info!("Start fetching...");
 
     let mut lp = Core::new().unwrap();
     let handle = lp.handle();
 
+    let robots_ttl = Duration::from_secs(raider.robots_ttl);
+    let dns_cache_ttl = Duration::from_secs(raider.dns_cache_ttl);
+    let connect_timeout = Duration::from_secs(raider.connect_timeout);
+    let request_timeout = Duration::from_secs(raider.request_timeout);
+    let host_cooldown = Duration::from_secs(raider.host_cooldown);
+    let max_block = Duration::from_secs(u64::from(raider.max_interval));
+    let limiter = Limiter::new(&handle, raider.host_rate, raider.host_burst, raider.host_failure_threshold,
+                               host_cooldown, max_block);
+    let client = Client::new(&handle, &limiter, raider.max_connections, raider.max_host_connections,
+                             dns_cache_ttl, connect_timeout, request_timeout);
+
+    let robots = Robots::new(&handle, &client, robots_ttl, Duration::from_secs(raider.max_crawl_delay));
+    let downloads = Semaphore::new(raider.download_concurrency);
+    let slow_extraction = Duration::from_millis(raider.slow_extraction);
+    let extractor = Extractor::new(raider.extraction_threads, raider.extraction_queue, slow_extraction);
+    let mut reported_at = time::get_time();
+
+    // Only one visit per feed at a time, so updates of the feed are sent in order.
+    let in_flight = RefCell::new(HashSet::new());
+
+    let (client, robots, downloads, extractor, dedup) = (&client, &robots, &downloads, &extractor, &dedup);
+    let (producer, queue) = (&producer, &queue);
+
     let process = stream
-        // TODO: ideally, in the case of redirect, we should change the url.
-        // TODO: should we fetch feeds concurrently?
-        .and_then(|feed| fetch_entries(&handle, feed))
-        .and_then(|(feed, entries)| fetch_documents(&handle, feed, entries))
-        .for_each(|(mut feed, entries)| {
-            info!("Visited {} and collected {} new entries", feed.url, entries.len());
-
-            if let Some(augmented) = entries.iter().map(|entry| entry.published).max() {
-                feed.augmented = augmented;
+        .filter(|feed| {
+            let fresh = in_flight.borrow_mut().insert(Key::from(feed.url.clone()));
+
+            if !fresh {
+                warn!("{} is already being visited, skipping the copy", feed.url);
+            }
+
+            fresh
+        })
+        .map(move |feed| {
+            fetch_entries(client, raider, extractor, feed)
+                .map(move |mut visit| {
+                    visit.entries = drop_duplicates(&mut dedup.borrow_mut(), visit.entries);
+                    visit
+                })
+                .and_then(move |visit| fetch_documents(client, raider, robots, downloads, extractor, visit))
+        })
+        .buffer_unordered(raider.feed_concurrency)
+        .for_each(|visit| {
+            let Visit { feed, mut entries, failed, moved_from, .. } = visit;
+
+            in_flight.borrow_mut().remove(&Key::from(moved_from.clone().unwrap_or_else(|| feed.url.clone())));
+
+            info!("Visited {} and collected {} new entries, {} of them are postponed. {} of last new have full text",
+                  feed.url, entries.len() + failed.len(), failed.len(), feed.full_text);
+
+            // Failed entries are parked, so the feed has already moved past them.
+            for (entry, undated) in failed {
+                match park(raider, entry, undated, 1) {
+                    Ok(entry) => entries.push(entry),
+                    Err(retry) => queue.borrow_mut().push(retry)
+                }
+            }
+
+            // The feed remembers parked entries as seen, so they must be on disk before the feed is sent.
+            if let Err(error) = queue.borrow_mut().save() {
+                error!("Cannot save the retry queue: {}", error);
+            }
+
+            let mut producer = producer.borrow_mut();
+
+            // Keys ignore schemes, so moving to https often keeps the same key.
+            let stale_key = moved_from.map(Key::from)
+                .and_then(|key| if key != Key::from(feed.url.clone()) { Some(key) } else { None });
+
+            send_feed(&mut *producer, &config.topics.feeds, feed);
+
+            if let Some(key) = stale_key {
+                send_tombstone(&mut *producer, &config.topics.feeds, key);
+            }
+
+            send_entries(&mut *producer, &config.topics.entries, entries);
+
+            let now = time::get_time();
+
+            if (now - saved_at).num_seconds() >= raider.dedup_save_interval {
+                if let Err(error) = dedup.borrow().save() {
+                    error!("Cannot save the dedup filter: {}", error);
+                }
+
+                saved_at = now;
             }
 
-            send_feed(&mut producer, feed);
-            send_entries(&mut producer, entries);
+            if (now - reported_at).num_seconds() >= raider.extraction_report_interval {
+                extractor.report(10);
+                reported_at = now;
+            }
 
             Ok(())
         });
 
-    lp.run(process).unwrap();
+    let retries = Interval::new(Duration::from_secs(RETRY_CHECK_PERIOD), &handle).unwrap()
+        .map_err(|error| error!("The retry timer is failed: {}", error))
+        .map(move |_| {
+            let mut queue = queue.borrow_mut();
+            let due = queue.pop_due(time::get_time().sec);
+
+            if let Err(error) = queue.save() {
+                error!("Cannot save the retry queue: {}", error);
+            }
+
+            stream::iter_ok::<_, ()>(due)
+        })
+        .flatten()
+        .map(move |(id, retry)| {
+            retry_document(client, raider, robots, downloads, extractor, retry).map(move |result| (id, result))
+        })
+        .buffer_unordered(raider.download_concurrency)
+        .for_each(move |(id, result)| {
+            let mut queue = queue.borrow_mut();
+
+            queue.finish(id);
+
+            match result {
+                Ok(entry) => send_entries(&mut *producer.borrow_mut(), &config.topics.entries, vec![entry]),
+                Err(retry) => queue.push(retry)
+            }
+
+            Ok(())
+        });
+
+    let limiter = &limiter;
+
+    let evictions = Interval::new(Duration::from_secs(HOST_EVICTION_PERIOD), &handle).unwrap()
+        .map_err(|error| error!("The eviction timer is failed: {}", error))
+        .for_each(move |_| {
+            limiter.evict_idle();
+            client.evict_idle();
+            robots.evict_expired();
+            Ok(())
+        });
+
+    lp.run(process.join3(retries, evictions)).unwrap();
 }
 
 fn main() {
     logger::init().unwrap();
 
+    let config = config::load().unwrap();
+    let scheduling_config = config.clone();
+
     let (scheduler, stream) = Scheduler::new();
 
-    thread::spawn(move || scheduling(scheduler));
-    fetching(stream);
+    thread::spawn(move || scheduling(&scheduling_config, scheduler));
+    fetching(&config, stream);
 }
 
 #[test]
 fn it_estimates_interval() {
-    assert_eq!(estimate_interval(MIN_INTERVAL, 30, 0), (MIN_INTERVAL as f32 / PROMPTNESS) as u32);
+    let config = RaiderConfig::default();
+    let (min_interval, max_interval, promptness) =
+        (config.min_interval, config.max_interval, config.promptness);
+
+    assert_eq!(estimate_interval(&config, None, min_interval, 30, 0), (min_interval as f32 / promptness) as u32);
 
     // Keypoints.
-    let some_prev = MIN_INTERVAL + 2048;
-    assert_eq!(estimate_interval(some_prev, 10000, ((1. - PROMPTNESS) * 10000.) as u32), some_prev);
-    assert_eq!(estimate_interval(MIN_INTERVAL, 30, 30), MIN_INTERVAL);
-    assert_eq!(estimate_interval(MIN_INTERVAL, 1, 1), MIN_INTERVAL);
-    assert_eq!(estimate_interval(MAX_INTERVAL, 30, 0), MAX_INTERVAL);
-    assert_eq!(estimate_interval(MAX_INTERVAL, 1, 0), MAX_INTERVAL);
-    assert_eq!(estimate_interval(MIN_INTERVAL + 42, 0, 0), 2 * MIN_INTERVAL + 42);
-    assert_eq!(estimate_interval(0, 30, 30), MIN_INTERVAL);
-    assert_eq!(estimate_interval(0, 30, 0), MIN_INTERVAL);
-    assert_eq!(estimate_interval(0, 1, 0), MIN_INTERVAL);
-    assert_eq!(estimate_interval(0, 0, 0), MIN_INTERVAL);
+    let some_prev = min_interval + 2048;
+    assert_eq!(estimate_interval(&config, None, some_prev, 10000, ((1. - promptness) * 10000.) as u32), some_prev);
+    assert_eq!(estimate_interval(&config, None, min_interval, 30, 30), min_interval);
+    assert_eq!(estimate_interval(&config, None, min_interval, 1, 1), min_interval);
+    assert_eq!(estimate_interval(&config, None, max_interval, 30, 0), max_interval);
+    assert_eq!(estimate_interval(&config, None, max_interval, 1, 0), max_interval);
+    assert_eq!(estimate_interval(&config, None, min_interval + 42, 0, 0), 2 * min_interval + 42);
+    assert_eq!(estimate_interval(&config, None, 0, 30, 30), min_interval);
+    assert_eq!(estimate_interval(&config, None, 0, 30, 0), min_interval);
+    assert_eq!(estimate_interval(&config, None, 0, 1, 0), min_interval);
+    assert_eq!(estimate_interval(&config, None, 0, 0, 0), min_interval);
+}
+
+#[test]
+fn it_respects_publisher_hints() {
+    let config = RaiderConfig::default();
+    let (min_interval, max_interval) = (config.min_interval, config.max_interval);
+
+    let hint = Some(3 * min_interval);
+    assert_eq!(estimate_interval(&config, hint, min_interval, 30, 30), 3 * min_interval);
+    assert_eq!(estimate_interval(&config, hint, 0, 0, 0), 3 * min_interval);
+    assert_eq!(estimate_interval(&config, hint, 5 * min_interval, 30, 0), 10 * min_interval);
+
+    assert_eq!(estimate_interval(&config, Some(min_interval / 2), 0, 30, 30), min_interval);
+    assert_eq!(estimate_interval(&config, Some(2 * max_interval), 0, 30, 30), max_interval);
+}
+
+#[test]
+fn it_delays_retries() {
+    use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+
+    let config = RaiderConfig::default();
+    let (min_interval, max_interval, retry_interval) =
+        (config.min_interval, config.max_interval, config.retry_interval);
+
+    let status = |code, retry_after| FetchError::Status { code, retry_after };
+
+    assert_eq!(retry_delay(&config, 0, &status(404, None)), max_interval);
+    assert_eq!(retry_delay(&config, 0, &status(410, None)), max_interval);
+    assert_eq!(retry_delay(&config, 0, &status(429, Some(120))), 120);
+    assert_eq!(retry_delay(&config, 0, &status(503, Some(2 * max_interval))), max_interval);
+    assert_eq!(retry_delay(&config, 0, &status(429, None)), min_interval);
+    assert_eq!(retry_delay(&config, 1, &status(429, None)), 2 * min_interval);
+    assert_eq!(retry_delay(&config, 0, &status(502, None)), retry_interval);
+
+    assert_eq!(retry_delay(&config, 0, &FetchError::Timeout), retry_interval);
+    assert_eq!(retry_delay(&config, 2, &FetchError::Timeout), 4 * retry_interval);
+    assert_eq!(retry_delay(&config, 100, &FetchError::Timeout), max_interval);
+
+    let error = FetchError::Network(IoError::new(IoErrorKind::ConnectionRefused, "refused"));
+    assert_eq!(retry_delay(&config, 0, &error), retry_interval);
+    assert_eq!(retry_delay(&config, 0, &FetchError::Parse("oops".to_owned())), min_interval);
+    assert_eq!(retry_delay(&config, 0, &FetchError::TooLarge(1024)), min_interval);
+    assert_eq!(retry_delay(&config, 3, &FetchError::Blocked(300)), 300);
+}
+
+#[test]
+fn it_parks_failed_entries() {
+    let config = RaiderConfig::default();
+
+    let entry = entry("http://example.com/1");
+
+    let retry = park(&config, entry, Some(3600), 1).err().unwrap();
+    assert_eq!(retry.attempts, 1);
+    assert_eq!(retry.undated, Some(3600));
+    assert!(retry.retry_at >= time::get_time().sec + config.retry_interval as i64 - 1);
+
+    let last = config.max_document_retries;
+    let retry = park(&config, retry.entry, None, last).err().unwrap();
+    let entry = park(&config, retry.entry, None, last + 1).ok().unwrap();
+    assert_eq!(entry.content, "Feed content");
+}
+
+#[test]
+fn it_parks_entries_of_unknown_robots() {
+    assert!(unpermitted(Permit::Unknown, entry("http://example.com/1")).is_err());
+    assert!(unpermitted(Permit::Disallowed, entry("http://example.com/1")).is_ok());
+}
+
+#[cfg(test)]
+fn feed() -> Feed {
+    Feed::new(Url::parse("http://example.com").unwrap(), Url::parse("http://example.com/rss").unwrap())
+}
+
+#[cfg(test)]
+fn entry(url: &str) -> Entry {
+    Entry {
+        source: Url::parse("http://example.com").unwrap(),
+        url: Url::parse(url).unwrap(),
+        title: "Title".to_owned(),
+        author: None,
+        content: "Feed content".to_owned(),
+        content_source: ContentSource::Feed,
+        blocks: Vec::new(),
+        published: Timespec::new(0, 0)
+    }
+}
+
+#[test]
+fn it_finds_new_items_by_ids() {
+    use feed::Item;
+
+    let config = RaiderConfig::default();
+
+    let item = |id: Option<&str>, link: &str, pub_date: Option<&str>| Item {
+        id: id.map(str::to_owned),
+        link: Some(link.to_owned()),
+        title: Some("Title".to_owned()),
+        pub_date: pub_date.map(str::to_owned),
+        ..Item::default()
+    };
+
+    let channel = |items| Channel { items, ..Channel::default() };
+
+    let first = vec![
+        item(Some("1"), "http://example.com/1", Some("Wed, 17 May 2017 05:02:12 GMT")),
+        item(None, "http://example.com/2", None),
+        item(Some("1"), "http://example.com/1", Some("Wed, 17 May 2017 05:02:12 GMT"))
+    ];
+
+    let Visit { feed, entries, undated, .. } = disassemble_channel(&config, feed(), channel(first));
+
+    assert_eq!(entries.len(), 2);
+    assert_eq!(feed.size, 2);
+    assert_eq!(feed.seen, vec![bloom::hash(b"1"), bloom::hash(b"http://example.com/2")]);
+    assert!(undated.contains(&entries[1].url));
+
+    // Back-dated and equally dated items are new too.
+    let second = vec![
+        item(Some("3"), "http://example.com/3", Some("Wed, 17 May 2017 05:02:12 GMT")),
+        item(Some("4"), "http://example.com/4", Some("Tue, 16 May 2017 05:02:12 GMT")),
+        item(Some("1"), "http://example.com/1", Some("Wed, 17 May 2017 05:02:12 GMT")),
+        item(None, "http://example.com/2", None)
+    ];
+
+    let Visit { feed, entries, undated, .. } = disassemble_channel(&config, feed, channel(second));
+
+    assert_eq!(entries.iter().map(|entry| entry.url.as_str()).collect::<Vec<_>>(), vec![
+        "http://example.com/3", "http://example.com/4"
+    ]);
+    assert!(undated.is_empty());
+    assert_eq!(feed.seen.len(), 4);
+    assert_eq!(feed.augmented, Timespec::new(1494997332, 0));
+}
+
+#[test]
+fn it_remembers_seen_items() {
+    let ids = |ids: &[&str]| ids.iter().map(|id| bloom::hash(id.as_bytes())).collect::<Vec<_>>();
+
+    assert_eq!(remember_seen(ids(&["c", "b"]), ids(&["b", "a", "z"]), 3), ids(&["c", "b", "a"]));
+    assert_eq!(remember_seen(ids(&["c", "b"]), ids(&["a"]), 1), ids(&["c", "b"]));
+
+    // Feeds visited before tracking ids fall back to the latest date.
+    let mut feed = feed();
+    feed.augmented = Timespec::new(1494997332, 0);
+
+    let channel = Channel {
+        items: vec![
+            feed::Item {
+                link: Some("http://example.com/1".to_owned()),
+                title: Some("Title".to_owned()),
+                pub_date: Some("Wed, 17 May 2017 05:02:12 GMT".to_owned()),
+                ..feed::Item::default()
+            }
+        ],
+        ..Channel::default()
+    };
+
+    let Visit { feed, entries, .. } = disassemble_channel(&RaiderConfig::default(), feed, channel);
+
+    assert!(entries.is_empty());
+    assert_eq!(feed.seen, ids(&["http://example.com/1"]));
+}
+
+#[test]
+fn it_fits_feeds_into_messages() {
+    // `max.message.bytes` of the feeds topic.
+    const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+    let long = format!("http://example.com/{}", "a".repeat(2000));
+    let mut feed = Feed::new(Url::parse(&long).unwrap(), Url::parse(&long).unwrap());
+
+    feed.etag = Some("e".repeat(200));
+    feed.last_modified = Some("Wed, 17 May 2017 05:02:12 GMT".to_owned());
+    feed.skip_hours = (0..24).collect();
+    feed.skip_days = (0..7).collect();
+    feed.seen = remember_seen(vec![u64::max_value(); 3 * MAX_SEEN_ITEMS], Vec::new(), 10 * MAX_SEEN_ITEMS);
+
+    assert_eq!(feed.seen.len(), MAX_SEEN_ITEMS);
+    assert!(serde_json::to_vec(&feed).unwrap().len() <= MAX_MESSAGE_SIZE);
+}
+
+#[test]
+fn it_fits_entries_into_messages() {
+    use common::messages::{Block, BlockKind};
+
+    let mut entry = entry("http://example.com/1");
+
+    entry.content = "a".repeat(MAX_ENTRY_SIZE / 2);
+    entry.blocks = vec![Block { kind: BlockKind::Paragraph, text: entry.content.clone(), links: Vec::new() }];
+
+    let value = serialize_entry(entry.clone()).unwrap();
+    let fitted: Entry = serde_json::from_slice(&value).unwrap();
+
+    assert!(value.len() <= MAX_ENTRY_SIZE);
+    assert!(fitted.blocks.is_empty());
+    assert_eq!(fitted.content, entry.content);
+
+    entry.blocks.clear();
+    entry.content = "a".repeat(MAX_ENTRY_SIZE);
+
+    assert!(serialize_entry(entry).is_none());
+}
+
+#[test]
+fn it_uses_full_text() {
+    use feed::Item;
+
+    let config = RaiderConfig { min_full_text_length: 10, ..RaiderConfig::default() };
+
+    let item = |link: &str, content: Option<&str>, full_text: Option<&str>| Item {
+        link: Some(link.to_owned()),
+        title: Some("Title".to_owned()),
+        description: Some("Teaser".to_owned()),
+        content: content.map(str::to_owned),
+        full_text: full_text.map(str::to_owned),
+        ..Item::default()
+    };
+
+    let channel = Channel {
+        items: vec![
+            item("http://example.com/1", None, Some("The whole article")),
+            item("http://example.com/2", Some("<p>The whole article</p>"), None),
+            item("http://example.com/3", Some("<p>Short</p>"), None)
+        ],
+        ..Channel::default()
+    };
+
+    let Visit { feed, entries, full_text, .. } = disassemble_channel(&config, feed(), channel);
+
+    assert_eq!(feed.full_text, 2);
+    assert_eq!(full_text.len(), 2);
+    assert!(!full_text.contains(&entries[2].url));
+    assert_eq!(entries[0].content, "The whole article");
+}
+
+#[test]
+fn it_clamps_future_dates() {
+    use feed::Item;
+
+    let config = RaiderConfig::default();
+
+    let item = |link: &str, pub_date: &str| Item {
+        link: Some(link.to_owned()),
+        title: Some("Title".to_owned()),
+        pub_date: Some(pub_date.to_owned()),
+        ..Item::default()
+    };
+
+    let channel = Channel {
+        items: vec![
+            item("http://example.com/1", "Sat, 17 May 2117 05:02:12 +0300"),
+            item("http://example.com/2", "2017-05-17 08:02:12")
+        ],
+        ..Channel::default()
+    };
+
+    let Visit { feed, entries, .. } = disassemble_channel(&config, feed(), channel);
+
+    assert_eq!(entries.len(), 2);
+    assert!(entries[0].published <= time::get_time());
+    assert_eq!(feed.augmented, entries[0].published);
+    assert_eq!(entries[1].published, Timespec::new(1494997332 + 3 * 3600, 0));
+
+    // The zone is learned for the next visits.
+    assert_eq!(feed.timezone, Some(3 * 3600));
+    assert_eq!(default_offset(&config, &feed), 3 * 3600);
+}
+
+#[test]
+fn it_resolves_relative_links() {
+    use feed::Item;
+
+    let config = RaiderConfig::default();
+
+    let item = |link: &str| Item {
+        link: Some(link.to_owned()),
+        title: Some("Title".to_owned()),
+        pub_date: Some("Wed, 17 May 2017 05:02:12 GMT".to_owned()),
+        ..Item::default()
+    };
+
+    let channel = |base: Option<&str>| Channel {
+        link: Some("/blog/".to_owned()),
+        base: base.map(str::to_owned),
+        items: vec![
+            item("posts/1"), item("/about"), item("https://other.com/2"), item(" "), item("tag:example.com,2017:3")
+        ],
+        ..Channel::default()
+    };
+
+    let urls = |entries: Vec<Entry>| entries.into_iter().map(|entry| entry.url.into_string()).collect::<Vec<_>>();
+
+    let Visit { feed: plain, entries, .. } = disassemble_channel(&config, feed(), channel(None));
+
+    assert_eq!(plain.source.as_str(), "http://example.com/blog/");
+    assert_eq!(urls(entries), vec![
+        "http://example.com/blog/posts/1", "http://example.com/about", "https://other.com/2"
+    ]);
+
+    let channel = channel(Some("http://example.org/news/"));
+    let Visit { feed: based, entries, .. } = disassemble_channel(&config, feed(), channel);
+
+    assert_eq!(based.source.as_str(), "http://example.org/blog/");
+    assert_eq!(urls(entries)[0], "http://example.org/news/posts/1");
+}
+
+#[test]
+fn it_chooses_better_content() {
+    let mut entry = entry("http://example.com/1");
+    entry.title = "Central bank raises the key rate".to_owned();
+    entry.content = "<p>The central bank raised the <a href=\"/rate\">key rate</a>.</p>".to_owned();
+
+    let extraction = |text: &str, linked| Extraction { text: text.to_owned(), blocks: Vec::new(), linked };
+
+    let navigation = "Main page News Sport Weather Contacts";
+    let linked = navigation.chars().filter(|c| !c.is_whitespace()).count();
+
+    choose_content(&mut entry, extraction(navigation, linked));
+    assert_eq!(entry.content_source, ContentSource::Feed);
+
+    choose_content(&mut entry, extraction("", 0));
+    assert_eq!(entry.content_source, ContentSource::Feed);
+
+    let article = "The central bank raised the key rate by half a point on Friday, citing inflation risks.";
+
+    choose_content(&mut entry, extraction(article, 0));
+    assert_eq!(entry.content_source, ContentSource::Page);
+    assert_eq!(entry.content, article);
+}
+
+#[test]
+fn it_postpones_skipped_hours() {
+    let mut feed = feed();
+
+    // Wed, 17 May 2017 05:02:12 GMT.
+    let at = 1494997332;
+    let hour = 3600;
+
+    assert_eq!(postpone(&feed, at), at);
+
+    feed.skip_hours = vec![5, 6, 8];
+    assert_eq!(postpone(&feed, at), at - 132 + 2 * hour);
+
+    // Wednesday and Thursday.
+    feed.skip_days = vec![3, 4];
+    assert_eq!(postpone(&feed, at), at - 5 * hour - 132 + 2 * 24 * hour);
+
+    feed.skip_days = (0..7).collect();
+    assert_eq!(postpone(&feed, at), at);
 }
@@ -0,0 +1,111 @@
+// Names of meta tags with the publication date: Open Graph, schema.org microdata and Dublin Core.
+const DATE_PROPERTIES: &[&str] = &["article:published_time", "datepublished", "dc.date.issued", "dc.date"];
+
+// Looks for the publication date in the page's meta tags and then in JSON-LD.
+pub fn published_time(document: &str) -> Option<String> {
+    let lower = document.to_ascii_lowercase();
+
+    for (start, _) in lower.match_indices("<meta") {
+        let end = lower[start..].find('>').map_or(lower.len(), |end| start + end);
+        let tag = &document[start..end];
+
+        let name = attribute(tag, "property")
+            .or_else(|| attribute(tag, "name"))
+            .or_else(|| attribute(tag, "itemprop"))
+            .map(str::to_ascii_lowercase);
+
+        if !name.map_or(false, |name| DATE_PROPERTIES.contains(&name.as_str())) {
+            continue;
+        }
+
+        match attribute(tag, "content").map(str::trim) {
+            Some(content) if !content.is_empty() => return Some(content.to_owned()),
+            _ => continue
+        }
+    }
+
+    json_ld_date(document)
+}
+
+fn json_ld_date(document: &str) -> Option<String> {
+    let key = "\"datePublished\"";
+    let mut rest = document;
+
+    while let Some(pos) = rest.find(key) {
+        rest = rest[pos + key.len()..].trim_left();
+
+        if !rest.starts_with(':') {
+            continue;
+        }
+
+        let value = rest[1..].trim_left();
+
+        if !value.starts_with('"') {
+            continue;
+        }
+
+        if let Some(end) = value[1..].find('"') {
+            return Some(value[1..end + 1].to_owned());
+        }
+    }
+
+    None
+}
+
+// The value of `name="value"`, `name='value'` or `name=value`. Names are case-insensitive.
+pub fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let lower = tag.to_ascii_lowercase();
+    let mut from = 0;
+
+    while let Some(pos) = lower[from..].find(name) {
+        let start = from + pos;
+        from = start + name.len();
+
+        // Skip matches inside other names and values.
+        if !lower[..start].ends_with(char::is_whitespace) {
+            continue;
+        }
+
+        let rest = lower[from..].trim_left();
+
+        if !rest.starts_with('=') {
+            continue;
+        }
+
+        let value = tag[tag.len() - rest.len() + 1..].trim_left();
+
+        let (value, end) = match value.chars().next() {
+            Some(quote) if quote == '"' || quote == '\'' => (&value[1..], value[1..].find(quote)),
+            _ => (value, value.find(|c: char| c.is_whitespace() || c == '>'))
+        };
+
+        return Some(&value[..end.unwrap_or_else(|| value.len())]);
+    }
+
+    None
+}
+
+#[test]
+fn it_finds_date_in_meta_tags() {
+    let document = r#"<html><head>
+        <meta property="og:title" content="Title">
+        <META Property="article:published_time" content="2017-05-17T08:02:12+03:00" />
+    </head></html>"#;
+
+    assert_eq!(published_time(document), Some("2017-05-17T08:02:12+03:00".to_owned()));
+
+    let document = "<meta itemprop='datePublished' content='2017-05-17'><meta name=date content=none>";
+    assert_eq!(published_time(document), Some("2017-05-17".to_owned()));
+
+    assert_eq!(published_time("<meta name=\"description\" content=\"2017-05-17\">"), None);
+}
+
+#[test]
+fn it_finds_date_in_json_ld() {
+    let document = r#"<script type="application/ld+json">
+        {"@type": "NewsArticle", "datePublished" : "2017-05-17T08:02:12Z", "dateModified": "2017-05-18"}
+    </script>"#;
+
+    assert_eq!(published_time(document), Some("2017-05-17T08:02:12Z".to_owned()));
+    assert_eq!(published_time("{\"datePublished\": null}"), None);
+}
@@ -0,0 +1,107 @@
+use std::collections::HashSet;
+
+use kuchiki::{self, NodeRef};
+use kuchiki::traits::*;
+
+// Title words shorter than this are mostly prepositions.
+const MIN_TITLE_WORD: usize = 3;
+
+// Higher is better, zero means there is no text. `linked` is the number of chars inside links.
+// Long texts win, but navigation and texts unrelated to the title lose most of the score.
+pub fn score(title: &str, text: &str, linked: usize) -> f64 {
+    let chars = count_chars(text);
+
+    if chars == 0 {
+        return 0.;
+    }
+
+    let link_density = (linked as f64 / chars as f64).min(1.);
+
+    (1. + chars as f64).ln() * (1. - link_density) * (0.5 + 0.5 * title_overlap(title, text))
+}
+
+// Strips tags of HTML and counts chars inside links.
+pub fn html_text(html: &str) -> (String, usize) {
+    let document = kuchiki::parse_html().one(html);
+
+    let text = document.descendants()
+        .filter_map(|node| node.as_text().map(|text| text.borrow().clone()))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    (text, linked_chars(&document))
+}
+
+// The number of chars inside links. Every char is counted once, even inside nested links.
+pub fn linked_chars(node: &NodeRef) -> usize {
+    node.descendants()
+        .filter(|node| node.ancestors().any(|ancestor| is_link(&ancestor)))
+        .filter_map(|node| node.as_text().map(|text| count_chars(&text.borrow())))
+        .sum()
+}
+
+// The number of chars without spaces.
+pub fn text_length(html: &str) -> usize {
+    count_chars(&html_text(html).0)
+}
+
+// The share of title words found in the text, titles without words don't matter.
+fn title_overlap(title: &str, text: &str) -> f64 {
+    let title = words(title).into_iter()
+        .filter(|word| word.chars().count() >= MIN_TITLE_WORD)
+        .collect::<HashSet<_>>();
+
+    if title.is_empty() {
+        return 1.;
+    }
+
+    let text = words(text);
+    let found = title.iter().filter(|word| text.contains(*word)).count();
+
+    found as f64 / title.len() as f64
+}
+
+fn words(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+fn is_link(node: &NodeRef) -> bool {
+    node.as_element().map_or(false, |element| &*element.name.local == "a")
+}
+
+fn count_chars(text: &str) -> usize {
+    text.chars().filter(|c| !c.is_whitespace()).count()
+}
+
+#[test]
+fn it_strips_html() {
+    let (text, linked) = html_text("<p class=\"lead\">Some <a href=\"/\">linked <b>text</b></a></p><abbr>x</abbr>");
+
+    assert_eq!(text.split_whitespace().collect::<Vec<_>>(), vec!["Some", "linked", "text", "x"]);
+    assert_eq!(linked, 10);
+    assert_eq!(text_length("<p>Some <b>text</b></p>\n<br/>"), 8);
+}
+
+#[test]
+fn it_counts_linked_chars() {
+    // Only the link is linked, not the same words elsewhere.
+    assert_eq!(html_text("<p>Russia and <a href=\"/ru\">Russia</a> &amp; Russia</p>").1, 6);
+    assert_eq!(html_text("<a href=\"/\">one <abbr>two</abbr></a> three").1, 6);
+}
+
+#[test]
+fn it_scores_content() {
+    let title = "Central bank raises the key rate";
+    let article = "The central bank raised the key rate by half a point on Friday, citing inflation risks. \
+                   Analysts expected the decision after the rate had been kept unchanged for a year.";
+    let teaser = "The central bank raised the key rate.";
+    let navigation = "Main page News Sport Weather Contacts About us Advertising";
+
+    assert!(score(title, article, 0) > score(title, teaser, 0));
+    assert!(score(title, teaser, 0) > score(title, navigation, count_chars(navigation)));
+    assert!(score(title, teaser, 0) > score(title, navigation, 0));
+    assert_eq!(score(title, "  ", 0), 0.);
+}
@@ -0,0 +1,208 @@
+use std::cmp::{self, Ordering};
+use std::collections::{BinaryHeap, HashMap};
+use std::fs::{self, File};
+use std::io::{Write, BufReader, BufWriter, Error as IoError, ErrorKind as IoErrorKind, Result as IoResult};
+use std::path::{Path, PathBuf};
+
+use serde_json;
+
+use common::messages::Entry;
+
+// An entry whose page couldn't be fetched. It keeps the feed content.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Retry {
+    pub entry: Entry,
+    // The default offset for the page's date if the entry is undated.
+    #[serde(default)]
+    pub undated: Option<i32>,
+    // The number of failed attempts.
+    pub attempts: u32,
+    // Seconds since the epoch.
+    pub retry_at: i64
+}
+
+impl Retry {
+    // The delay is doubled after every attempt, but cannot exceed `max_delay`.
+    pub fn new(entry: Entry, undated: Option<i32>, attempts: u32, now: i64, base_delay: u32, max_delay: u32) -> Retry {
+        let factor = 1u32 << cmp::min(attempts.saturating_sub(1), 16);
+        let delay = cmp::min(base_delay.saturating_mul(factor), max_delay);
+
+        Retry { entry, undated, attempts, retry_at: now + i64::from(delay) }
+    }
+}
+
+impl PartialEq for Retry {
+    fn eq(&self, other: &Retry) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Retry {}
+
+impl PartialOrd for Retry {
+    fn partial_cmp(&self, other: &Retry) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// The earliest retry is on top of the heap.
+impl Ord for Retry {
+    fn cmp(&self, other: &Retry) -> Ordering {
+        (other.retry_at, &other.entry.url).cmp(&(self.retry_at, &self.entry.url))
+    }
+}
+
+// Parked entries survive restarts, the file is rewritten when the queue changes.
+pub struct RetryQueue {
+    path: PathBuf,
+    heap: BinaryHeap<Retry>,
+    // Retries being fetched right now by their ids. They are kept in the file until they finish.
+    running: HashMap<u64, Retry>,
+    next_id: u64,
+    dirty: bool
+}
+
+impl RetryQueue {
+    pub fn open<P: AsRef<Path>>(path: P) -> IoResult<RetryQueue> {
+        let path = path.as_ref().to_owned();
+
+        let retries: Vec<Retry> = match File::open(&path) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file))
+                .map_err(|error| IoError::new(IoErrorKind::InvalidData, error))?,
+            Err(ref error) if error.kind() == IoErrorKind::NotFound => Vec::new(),
+            Err(error) => return Err(error)
+        };
+
+        Ok(RetryQueue {
+            path,
+            heap: retries.into_iter().collect(),
+            running: HashMap::new(),
+            next_id: 0,
+            dirty: false
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.heap.len() + self.running.len()
+    }
+
+    pub fn push(&mut self, retry: Retry) {
+        self.heap.push(retry);
+        self.dirty = true;
+    }
+
+    // Returns due retries with ids to finish them, the same url can be parked twice.
+    pub fn pop_due(&mut self, now: i64) -> Vec<(u64, Retry)> {
+        let mut due = Vec::new();
+
+        while self.heap.peek().map_or(false, |retry| retry.retry_at <= now) {
+            let retry = self.heap.pop().unwrap();
+            let id = self.next_id;
+
+            self.next_id += 1;
+            self.running.insert(id, retry.clone());
+            due.push((id, retry));
+            self.dirty = true;
+        }
+
+        due
+    }
+
+    pub fn finish(&mut self, id: u64) {
+        if self.running.remove(&id).is_some() {
+            self.dirty = true;
+        }
+    }
+
+    pub fn save(&mut self) -> IoResult<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        let tmp_path = self.path.with_extension("tmp");
+
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        let retries = self.heap.iter().chain(self.running.values()).collect::<Vec<_>>();
+        serde_json::to_writer(&mut writer, &retries).map_err(|error| IoError::new(IoErrorKind::Other, error))?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+
+        // Replace atomically to survive crashes in the middle of writing.
+        fs::rename(tmp_path, &self.path)?;
+
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn entry(idx: u32) -> Entry {
+    let mut entry = ::entry(&format!("http://example.com/{}", idx));
+    entry.title = format!("Entry #{}", idx);
+    entry
+}
+
+#[test]
+fn it_backs_off() {
+    assert_eq!(Retry::new(entry(0), None, 1, 1000, 60, 3600).retry_at, 1060);
+    assert_eq!(Retry::new(entry(0), None, 2, 1000, 60, 3600).retry_at, 1120);
+    assert_eq!(Retry::new(entry(0), None, 4, 1000, 60, 3600).retry_at, 1480);
+    assert_eq!(Retry::new(entry(0), None, 10, 1000, 60, 3600).retry_at, 4600);
+    assert_eq!(Retry::new(entry(0), None, 100, 1000, 60, 3600).retry_at, 4600);
+}
+
+#[test]
+fn it_keeps_retries() {
+    use std::env;
+
+    let path = env::temp_dir().join("chokurei-retry-test.json");
+    let _ = fs::remove_file(&path);
+
+    let mut queue = RetryQueue::open(&path).unwrap();
+
+    queue.push(Retry { entry: entry(0), undated: None, attempts: 1, retry_at: 300 });
+    queue.push(Retry { entry: entry(1), undated: None, attempts: 1, retry_at: 100 });
+    queue.push(Retry { entry: entry(2), undated: None, attempts: 2, retry_at: 200 });
+
+    queue.save().unwrap();
+    let mut queue = RetryQueue::open(&path).unwrap();
+    assert_eq!(queue.len(), 3);
+
+    assert!(queue.pop_due(50).is_empty());
+
+    let due = queue.pop_due(200);
+    assert_eq!(due.iter().map(|&(_, ref retry)| retry.retry_at).collect::<Vec<_>>(), vec![100, 200]);
+    assert_eq!(due[1].1.entry.title, "Entry #2");
+    assert_eq!(queue.len(), 3);
+
+    queue.finish(due[0].0);
+    assert_eq!(queue.len(), 2);
+
+    // Running retries are saved until they finish.
+    queue.save().unwrap();
+    assert_eq!(RetryQueue::open(&path).unwrap().len(), 2);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn it_keeps_retries_of_the_same_url() {
+    use std::env;
+
+    let path = env::temp_dir().join("chokurei-retry-same-test.json");
+    let _ = fs::remove_file(&path);
+
+    let mut queue = RetryQueue::open(&path).unwrap();
+
+    queue.push(Retry { entry: entry(0), undated: None, attempts: 1, retry_at: 100 });
+    queue.push(Retry { entry: entry(0), undated: None, attempts: 2, retry_at: 100 });
+
+    let due = queue.pop_due(100);
+    assert_eq!(due.len(), 2);
+    assert_eq!(queue.len(), 2);
+
+    queue.finish(due[0].0);
+    assert_eq!(queue.len(), 1);
+    queue.finish(due[0].0);
+    assert_eq!(queue.len(), 1);
+}
@@ -0,0 +1,329 @@
+use std::cmp;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use futures::{future, Future};
+use futures::future::Shared;
+use tokio_core::reactor::{Handle, Timeout};
+use url::Url;
+
+use client::Client;
+use download;
+
+const AGENT_TOKEN: &str = "chokurei";
+
+// Unreachable robots.txt disallows everything (RFC 9309), but it's fetched again sooner than `ttl`.
+const ERROR_TTL: u64 = 10 * 60;
+
+type RulesFuture = Shared<Box<Future<Item=Rules, Error=()>>>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Permit {
+    Allowed,
+    Disallowed,
+    // robots.txt is unreachable, so nothing is allowed until it's fetched.
+    Unknown
+}
+
+#[derive(Debug, Default)]
+pub struct Rules {
+    // Pairs of (allow, pattern).
+    rules: Vec<(bool, String)>,
+    crawl_delay: Option<f32>,
+    unreachable: bool
+}
+
+impl Rules {
+    pub fn parse(text: &str, agent: &str) -> Rules {
+        let (mut specific, mut wildcard) = (Rules::default(), Rules::default());
+        let (mut is_specific, mut is_wildcard, mut found_specific) = (false, false, false);
+        let mut prev_was_agent = false;
+
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("");
+            let mut parts = line.splitn(2, ':');
+
+            let (field, value) = match (parts.next(), parts.next()) {
+                (Some(field), Some(value)) => (field.trim().to_lowercase(), value.trim()),
+                _ => continue
+            };
+
+            if field == "user-agent" {
+                // Consecutive user agents share the same group of rules.
+                if !prev_was_agent {
+                    is_specific = false;
+                    is_wildcard = false;
+                }
+
+                let name = value.split('/').next().unwrap_or("").trim();
+
+                is_wildcard |= name == "*";
+                is_specific |= name.eq_ignore_ascii_case(agent);
+                found_specific |= is_specific;
+                prev_was_agent = true;
+                continue;
+            }
+
+            prev_was_agent = false;
+
+            for (matched, rules) in vec![(is_specific, &mut specific), (is_wildcard, &mut wildcard)] {
+                if !matched {
+                    continue;
+                }
+
+                match field.as_str() {
+                    "allow" if !value.is_empty() => rules.rules.push((true, value.to_owned())),
+                    // An empty disallow means that everything is allowed.
+                    "disallow" if !value.is_empty() => rules.rules.push((false, value.to_owned())),
+                    "crawl-delay" => rules.crawl_delay = value.parse().ok(),
+                    _ => {}
+                }
+            }
+        }
+
+        if found_specific { specific } else { wildcard }
+    }
+
+    // The most specific (longest) rule wins, allowing rules win in the case of a tie.
+    pub fn is_allowed(&self, path: &str) -> bool {
+        self.rules.iter()
+            .filter(|&&(_, ref pattern)| matches(pattern, path))
+            .max_by_key(|&&(allow, ref pattern)| (pattern.len(), allow))
+            .map_or(true, |&(allow, _)| allow)
+    }
+
+    pub fn unreachable() -> Rules {
+        Rules { unreachable: true, ..Rules::default() }
+    }
+
+    pub fn permit(&self, path: &str) -> Permit {
+        if self.unreachable {
+            Permit::Unknown
+        } else if self.is_allowed(path) {
+            Permit::Allowed
+        } else {
+            Permit::Disallowed
+        }
+    }
+
+    pub fn crawl_delay(&self) -> Option<f32> {
+        self.crawl_delay
+    }
+}
+
+// Supports `*` wildcards and the `$` anchor.
+fn matches(pattern: &str, path: &str) -> bool {
+    let anchored = pattern.ends_with('$');
+    let pattern = pattern.trim_right_matches('$');
+
+    let segments = pattern.split('*').collect::<Vec<_>>();
+    let (last, init) = segments.split_last().unwrap();
+
+    if init.is_empty() {
+        return if anchored { path == *last } else { path.starts_with(last) };
+    }
+
+    if !path.starts_with(init[0]) {
+        return false;
+    }
+
+    let mut pos = init[0].len();
+
+    for segment in &init[1..] {
+        match path[pos..].find(segment) {
+            Some(idx) => pos += idx + segment.len(),
+            None => return false
+        }
+    }
+
+    if anchored {
+        path.len() >= pos + last.len() && path.ends_with(last)
+    } else {
+        path[pos..].contains(last)
+    }
+}
+
+#[derive(Clone)]
+pub struct Robots {
+    handle: Handle,
+    client: Client,
+    ttl: Duration,
+    max_delay: Duration,
+    cache: Rc<RefCell<HashMap<String, (Instant, RulesFuture)>>>,
+    slots: Rc<RefCell<HashMap<String, Instant>>>
+}
+
+impl Robots {
+    pub fn new(handle: &Handle, client: &Client, ttl: Duration, max_delay: Duration) -> Robots {
+        Robots {
+            handle: handle.clone(),
+            client: client.clone(),
+            ttl,
+            max_delay,
+            cache: Rc::new(RefCell::new(HashMap::new())),
+            slots: Rc::new(RefCell::new(HashMap::new()))
+        }
+    }
+
+    // Waits for the crawl delay if the url is allowed.
+    pub fn permit(&self, url: &Url) -> impl Future<Item=Permit, Error=()> + 'static {
+        let origin = url.origin().ascii_serialization();
+
+        let path = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_owned()
+        };
+
+        let robots = self.clone();
+
+        self.rules(url, &origin).then(move |rules| {
+            let (permit, delay) = match rules {
+                Ok(rules) => (rules.permit(&path), rules.crawl_delay()),
+                Err(_) => (Permit::Allowed, None)
+            };
+
+            if permit != Permit::Allowed {
+                return future::Either::A(future::ok(permit));
+            }
+
+            let wait = robots.reserve(&origin, delay);
+            let timeout = Timeout::new(wait, &robots.handle).unwrap();
+
+            future::Either::B(timeout.then(|_| Ok(Permit::Allowed)))
+        })
+    }
+
+    fn rules(&self, url: &Url, origin: &str) -> RulesFuture {
+        let now = Instant::now();
+        let mut cache = self.cache.borrow_mut();
+
+        if let Some(&(expires, ref rules)) = cache.get(origin) {
+            if expires > now {
+                return rules.clone();
+            }
+        }
+
+        let mut robots_url = url.clone();
+        robots_url.set_path("/robots.txt");
+        robots_url.set_query(None);
+        robots_url.set_fragment(None);
+
+        debug!("  Fetching {}...", robots_url);
+
+        let cache_ref = self.cache.clone();
+        let origin_key = origin.to_owned();
+
+        let rules = download::robots(&self.client, &robots_url).then(move |result| {
+            Ok(match result {
+                Ok(Some(text)) => Rules::parse(&text, AGENT_TOKEN),
+                // 4xx statuses mean there are no restrictions.
+                Ok(None) => Rules::default(),
+                Err(error) => {
+                    warn!("Fetching {} is failed, nothing is allowed for now: {}", robots_url, error);
+
+                    let expires = Instant::now() + Duration::from_secs(ERROR_TTL);
+
+                    if let Some(entry) = cache_ref.borrow_mut().get_mut(&origin_key) {
+                        entry.0 = cmp::min(entry.0, expires);
+                    }
+
+                    Rules::unreachable()
+                }
+            })
+        });
+
+        let rules = (Box::new(rules) as Box<Future<Item=Rules, Error=()>>).shared();
+
+        cache.insert(origin.to_owned(), (now + self.ttl, rules.clone()));
+
+        rules
+    }
+
+    // Forgets expired rules and passed crawl delays.
+    pub fn evict_expired(&self) {
+        let now = Instant::now();
+
+        self.cache.borrow_mut().retain(|_, &mut (expires, _)| expires > now);
+        self.slots.borrow_mut().retain(|_, &mut slot| slot > now);
+    }
+
+    // Returns how long to wait before the request to keep the crawl delay.
+    fn reserve(&self, origin: &str, delay: Option<f32>) -> Duration {
+        let delay = delay.map_or(Duration::from_secs(0), |secs| {
+            cmp::min(Duration::from_millis((secs.max(0.) * 1000.) as u64), self.max_delay)
+        });
+
+        let now = Instant::now();
+        let mut slots = self.slots.borrow_mut();
+
+        let slot = slots.get(origin).map_or(now, |&slot| cmp::max(slot, now));
+        slots.insert(origin.to_owned(), slot + delay);
+
+        slot - now
+    }
+}
+
+#[test]
+fn it_matches_patterns() {
+    assert!(matches("/", "/any"));
+    assert!(matches("/fish", "/fish.html"));
+    assert!(!matches("/fish", "/Fish"));
+    assert!(matches("/*.php", "/index.php?x=1"));
+    assert!(!matches("/*.php$", "/index.php?x=1"));
+    assert!(matches("/*.php$", "/dir/index.php"));
+    assert!(matches("/fish*", "/fish"));
+    assert!(matches("/a/*/c", "/a/b/c/d"));
+    assert!(!matches("/a/*/c", "/a/b/d"));
+    assert!(matches("/exact$", "/exact"));
+    assert!(!matches("/exact$", "/exact/"));
+}
+
+#[test]
+fn it_parses_rules_for_agent() {
+    let text = "
+        # Comment
+        User-agent: *
+        Disallow: /
+
+        User-agent: Googlebot
+        User-agent: Chokurei/1.0
+        Disallow: /private # inline comment
+        Allow: /private/public
+        Crawl-delay: 2.5
+    ";
+
+    let rules = Rules::parse(text, AGENT_TOKEN);
+
+    assert!(rules.is_allowed("/news/1"));
+    assert!(!rules.is_allowed("/private/1"));
+    assert!(rules.is_allowed("/private/public/1"));
+    assert_eq!(rules.crawl_delay(), Some(2.5));
+}
+
+#[test]
+fn it_falls_back_to_wildcard() {
+    let text = "
+        User-agent: Googlebot
+        Disallow:
+
+        User-agent: *
+        Disallow: /search
+        Disallow: /tmp/
+        Allow: /tmp/ok
+    ";
+
+    let rules = Rules::parse(text, AGENT_TOKEN);
+
+    assert!(rules.is_allowed("/news/1"));
+    assert!(!rules.is_allowed("/search?q=1"));
+    assert!(!rules.is_allowed("/tmp/1"));
+    assert!(rules.is_allowed("/tmp/ok"));
+    assert_eq!(rules.crawl_delay(), None);
+
+    assert!(Rules::parse("", AGENT_TOKEN).is_allowed("/"));
+    assert_eq!(rules.permit("/news/1"), Permit::Allowed);
+    assert_eq!(rules.permit("/search?q=1"), Permit::Disallowed);
+    assert_eq!(Rules::unreachable().permit("/news/1"), Permit::Unknown);
+}
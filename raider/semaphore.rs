@@ -0,0 +1,108 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use futures::{future, Future};
+use futures::unsync::oneshot;
+
+struct Inner {
+    available: usize,
+    waiters: VecDeque<oneshot::Sender<Permit>>
+}
+
+// Limits the number of concurrent operations on the reactor.
+#[derive(Clone)]
+pub struct Semaphore(Rc<RefCell<Inner>>);
+
+// The permit is released on drop.
+pub struct Permit(Option<Rc<RefCell<Inner>>>);
+
+impl Semaphore {
+    pub fn new(permits: usize) -> Semaphore {
+        Semaphore(Rc::new(RefCell::new(Inner {
+            available: permits,
+            waiters: VecDeque::new()
+        })))
+    }
+
+    pub fn acquire(&self) -> impl Future<Item=Permit, Error=()> + 'static {
+        let mut inner = self.0.borrow_mut();
+
+        if inner.available > 0 {
+            inner.available -= 1;
+            return future::Either::A(future::ok(Permit(Some(self.0.clone()))));
+        }
+
+        let (tx, rx) = oneshot::channel();
+        inner.waiters.push_back(tx);
+
+        future::Either::B(rx.map_err(|_| ()))
+    }
+
+    // Nobody holds or waits for permits and there are no other handles, so it can be dropped.
+    pub fn is_idle(&self) -> bool {
+        Rc::strong_count(&self.0) == 1 && self.0.borrow().waiters.is_empty()
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let inner = match self.0.take() {
+            Some(inner) => inner,
+            None => return
+        };
+
+        // Hand the permit over to the first waiter that is still alive.
+        loop {
+            let waiter = inner.borrow_mut().waiters.pop_front();
+
+            let waiter = match waiter {
+                Some(waiter) => waiter,
+                None => {
+                    inner.borrow_mut().available += 1;
+                    return;
+                }
+            };
+
+            match waiter.send(Permit(Some(inner.clone()))) {
+                Ok(()) => return,
+                Err(mut permit) => { permit.0.take(); }
+            }
+        }
+    }
+}
+
+#[test]
+fn it_limits_permits() {
+    let semaphore = Semaphore::new(2);
+
+    let a = semaphore.acquire().wait().unwrap();
+    let b = semaphore.acquire().wait().unwrap();
+
+    let c = semaphore.acquire();
+    let d = semaphore.acquire();
+    assert_eq!(semaphore.0.borrow().waiters.len(), 2);
+
+    drop(a);
+    let c = c.wait().unwrap();
+
+    // The permit isn't lost if the waiter is gone.
+    drop(d);
+    drop(b);
+    assert_eq!(semaphore.0.borrow().available, 1);
+
+    drop(c);
+    assert_eq!(semaphore.0.borrow().available, 2);
+}
+
+#[test]
+fn it_finds_idle() {
+    let semaphore = Semaphore::new(1);
+    assert!(semaphore.is_idle());
+
+    let permit = semaphore.acquire().wait().unwrap();
+    assert!(!semaphore.is_idle());
+
+    drop(permit);
+    assert!(semaphore.is_idle());
+}
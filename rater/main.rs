@@ -0,0 +1,123 @@
+extern crate common;
+#[macro_use]
+extern crate log;
+extern crate time;
+extern crate serde_json;
+extern crate kafka;
+extern crate url;
+
+use std::collections::HashMap;
+
+use kafka::consumer::{Consumer, FetchOffset};
+use kafka::producer::{Producer, Record, Partitioner};
+
+use common::logger;
+use common::config::{self, Config};
+use common::key::Key;
+use common::messages::{Fake, Rating};
+use rating::Rater;
+
+mod rating;
+
+fn send_rating<P: Partitioner>(producer: &mut Producer<P>, topic: &str, rating: Rating) {
+    let key: String = Key::from(rating.source.clone()).into();
+    let value = serde_json::to_vec(&rating).unwrap();
+
+    producer.send(&Record::from_key_value(topic, key, value)).unwrap();
+}
+
+// The last published rating of every source.
+fn recv_ratings(config: &Config) -> Vec<Rating> {
+    let mut consumer = Consumer::from_hosts(config.kafka.hosts())
+        .with_fallback_offset(FetchOffset::Earliest)
+        .with_topic(config.topics.ratings.clone())
+        .create().unwrap();
+
+    let mut ratings = HashMap::new();
+
+    loop {
+        let message_sets = consumer.poll().unwrap();
+
+        if message_sets.is_empty() {
+            break;
+        }
+
+        for message in message_sets.iter().flat_map(|ms| ms.messages()) {
+            match serde_json::from_slice::<Rating>(message.value) {
+                Ok(rating) => { ratings.insert(Key::from(rating.source.clone()), rating); },
+                Err(error) => error!("Invalid message on \"{}\" topic: {}", config.topics.ratings, error)
+            }
+        }
+    }
+
+    ratings.into_iter().map(|(_, rating)| rating).collect()
+}
+
+fn main() {
+    logger::init().unwrap();
+
+    let config = config::load().unwrap();
+    let topics = &config.topics;
+
+    // The state is rebuilt from the whole topic on start, so offsets aren't committed.
+    let mut consumer = Consumer::from_hosts(config.kafka.hosts())
+        .with_fallback_offset(FetchOffset::Earliest)
+        .with_topic(topics.fakes.clone())
+        .create().unwrap();
+
+    let mut producer = Producer::from_hosts(config.kafka.hosts()).create().unwrap();
+
+    let mut rater = Rater::new(config.rater.windows.clone());
+
+    for rating in recv_ratings(&config) {
+        rater.restore(rating);
+    }
+
+    let mut expired_at = time::get_time();
+    // Nothing is expired until the fakes topic is read, restored sources would be zeroed meanwhile.
+    let mut restoring = true;
+
+    info!("Waiting for new fakes...");
+
+    loop {
+        let message_sets = consumer.poll().unwrap();
+
+        if message_sets.is_empty() {
+            restoring = false;
+        }
+
+        for message_set in message_sets.iter() {
+            for message in message_set.messages() {
+                let fake = match serde_json::from_slice::<Fake>(message.value) {
+                    Ok(fake) => fake,
+                    Err(error) => {
+                        error!("Invalid message on \"{}\" topic: {}", topics.fakes, error);
+                        continue;
+                    }
+                };
+
+                let entry = fake.entry;
+
+                info!("Got fake {} from {} with score {:.3}", entry.url, entry.source, fake.score);
+
+                let key = Key::from(entry.url);
+                let now = time::get_time();
+
+                if let Some(rating) = rater.add_fake(entry.source, key, entry.published, fake.score, now) {
+                    send_rating(&mut producer, &topics.ratings, rating);
+                }
+            }
+        }
+
+        let now = time::get_time();
+
+        if !restoring && (now - expired_at).num_seconds() >= config.rater.expiration_period {
+            for rating in rater.expire(now) {
+                debug!("Rating of {} is expired", rating.source);
+                send_rating(&mut producer, &topics.ratings, rating);
+            }
+
+            expired_at = now;
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::{HashMap, VecDeque};
+
+use time::Timespec;
+use url::Url;
+
+use common::key::Key;
+use common::messages::{Rating, Penalty};
+
+struct Source {
+    url: Url,
+    // Sorted by the publication time.
+    fakes: VecDeque<(Timespec, Key, f32)>,
+    penalties: Vec<Penalty>
+}
+
+pub struct Rater {
+    windows: Vec<u32>,
+    sources: HashMap<Key, Source>
+}
+
+impl Rater {
+    pub fn new(mut windows: Vec<u32>) -> Rater {
+        windows.sort();
+        windows.dedup();
+
+        Rater {
+            windows,
+            sources: HashMap::new()
+        }
+    }
+
+    // Takes the last published rating of the source, so only changes are published after restart.
+    // Sources without live fakes get zero ratings on the next expiration.
+    pub fn restore(&mut self, rating: Rating) {
+        let key = Key::from(rating.source.clone());
+
+        self.sources.entry(key).or_insert_with(|| Source {
+            url: rating.source,
+            fakes: VecDeque::new(),
+            penalties: rating.penalties
+        });
+    }
+
+    pub fn add_fake(&mut self, source: Url, entry: Key, published: Timespec, points: f32,
+                    now: Timespec) -> Option<Rating>
+    {
+        if published.sec <= now.sec - self.max_window() {
+            return None;
+        }
+
+        let key = Key::from(source.clone());
+
+        let source = self.sources.entry(key).or_insert_with(|| Source {
+            url: source,
+            fakes: VecDeque::new(),
+            penalties: Vec::new()
+        });
+
+        // The same entry can be reported again with a higher score.
+        if let Some(fake) = source.fakes.iter_mut().find(|fake| fake.1 == entry) {
+            if fake.2 >= points {
+                return None;
+            }
+
+            fake.2 = points;
+            return update(source, &self.windows, now);
+        }
+
+        let position = source.fakes.iter()
+            .rposition(|&(ts, _, _)| ts <= published)
+            .map_or(0, |idx| idx + 1);
+
+        source.fakes.insert(position, (published, entry, points));
+
+        update(source, &self.windows, now)
+    }
+
+    pub fn expire(&mut self, now: Timespec) -> Vec<Rating> {
+        let windows = &self.windows;
+
+        let ratings = self.sources.values_mut()
+            .filter_map(|source| update(source, windows, now))
+            .collect();
+
+        self.sources.retain(|_, source| !source.fakes.is_empty());
+
+        ratings
+    }
+
+    fn max_window(&self) -> i64 {
+        self.windows.last().cloned().unwrap_or(0) as i64
+    }
+}
+
+fn update(source: &mut Source, windows: &[u32], now: Timespec) -> Option<Rating> {
+    let max_window = windows.last().cloned().unwrap_or(0) as i64;
+
+    while source.fakes.front().map_or(false, |&(ts, _, _)| ts.sec <= now.sec - max_window) {
+        source.fakes.pop_front();
+    }
+
+    let penalties = windows.iter().map(|&window| {
+        let points = source.fakes.iter()
+            .filter(|&&(ts, _, _)| ts.sec > now.sec - window as i64)
+            .map(|&(_, _, points)| points)
+            .sum();
+
+        Penalty { window, points }
+    }).collect::<Vec<_>>();
+
+    if penalties == source.penalties {
+        return None;
+    }
+
+    source.penalties = penalties.clone();
+
+    Some(Rating {
+        source: source.url.clone(),
+        penalties,
+        updated: now
+    })
+}
+
+#[cfg(test)]
+fn points(rating: &Rating) -> Vec<f32> {
+    rating.penalties.iter().map(|penalty| penalty.points).collect()
+}
+
+#[cfg(test)]
+fn key(entry: &str) -> Key {
+    Key::from(entry.to_owned())
+}
+
+#[test]
+fn it_sums_points_over_windows() {
+    let mut rater = Rater::new(vec![100, 10]);
+    let source = Url::parse("http://example.com").unwrap();
+    let now = Timespec::new(1000, 0);
+
+    let rating = rater.add_fake(source.clone(), key("a"), Timespec::new(995, 0), 1., now).unwrap();
+    assert_eq!(points(&rating), [1., 1.]);
+
+    let rating = rater.add_fake(source.clone(), key("b"), Timespec::new(950, 0), 0.5, now).unwrap();
+    assert_eq!(points(&rating), [1., 1.5]);
+
+    assert!(rater.add_fake(source.clone(), key("c"), Timespec::new(900, 0), 1., now).is_none());
+}
+
+#[test]
+fn it_separates_sources_by_key() {
+    let mut rater = Rater::new(vec![10]);
+    let now = Timespec::new(1000, 0);
+
+    let a = Url::parse("http://www.example.com/").unwrap();
+    let b = Url::parse("http://example.com").unwrap();
+    let c = Url::parse("http://example.org").unwrap();
+
+    rater.add_fake(a, key("a"), now, 1., now);
+    assert_eq!(points(&rater.add_fake(b, key("b"), now, 1., now).unwrap()), [2.]);
+    assert_eq!(points(&rater.add_fake(c, key("c"), now, 1., now).unwrap()), [1.]);
+}
+
+#[test]
+fn it_keeps_max_score_per_entry() {
+    let mut rater = Rater::new(vec![10]);
+    let source = Url::parse("http://example.com").unwrap();
+    let now = Timespec::new(1000, 0);
+
+    rater.add_fake(source.clone(), key("a"), now, 0.5, now);
+
+    assert!(rater.add_fake(source.clone(), key("a"), now, 0.3, now).is_none());
+
+    let rating = rater.add_fake(source.clone(), key("a"), now, 0.75, now).unwrap();
+    assert_eq!(points(&rating), [0.75]);
+}
+
+#[test]
+fn it_expires_old_fakes() {
+    let mut rater = Rater::new(vec![10, 100]);
+    let source = Url::parse("http://example.com").unwrap();
+
+    rater.add_fake(source.clone(), key("a"), Timespec::new(1000, 0), 1., Timespec::new(1000, 0));
+
+    assert!(rater.expire(Timespec::new(1005, 0)).is_empty());
+
+    let ratings = rater.expire(Timespec::new(1010, 0));
+    assert_eq!(ratings.len(), 1);
+    assert_eq!(points(&ratings[0]), [0., 1.]);
+
+    let ratings = rater.expire(Timespec::new(1100, 0));
+    assert_eq!(points(&ratings[0]), [0., 0.]);
+
+    assert!(rater.sources.is_empty());
+}
+
+#[test]
+fn it_zeroes_restored_sources() {
+    let mut rater = Rater::new(vec![10]);
+    let a = Url::parse("http://example.com").unwrap();
+    let b = Url::parse("http://example.org").unwrap();
+    let now = Timespec::new(1000, 0);
+
+    let rating = |source: &Url, points| Rating {
+        source: source.clone(),
+        penalties: vec![Penalty { window: 10, points }],
+        updated: Timespec::new(900, 0)
+    };
+
+    rater.restore(rating(&a, 1.));
+    rater.restore(rating(&b, 1.));
+
+    // The same rating isn't published again.
+    assert!(rater.add_fake(b.clone(), key("b"), now, 1., now).is_none());
+
+    let ratings = rater.expire(now);
+    assert_eq!(ratings.len(), 1);
+    assert_eq!(ratings[0].source, a);
+    assert_eq!(points(&ratings[0]), [0.]);
+
+    assert_eq!(rater.sources.len(), 1);
+}
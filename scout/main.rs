@@ -3,7 +3,6 @@ extern crate common;
 extern crate log;
 extern crate futures;
 extern crate tokio_core;
-extern crate time;
 extern crate serde_json;
 extern crate kafka;
 extern crate url;
@@ -14,7 +13,6 @@ use std::io::{Result as IoResult, Error as IoError, ErrorKind as IoErrorKind};
 use std::collections::HashSet;
 
 use url::Url;
-use time::Timespec;
 use tokio_core::reactor::Core;
 use tokio_core::net::{UdpSocket, UdpCodec};
 use futures::Stream;
@@ -22,12 +20,10 @@ use kafka::consumer::{Consumer, FetchOffset};
 use kafka::producer::{Producer, Record, Partitioner};
 
 use common::logger;
+use common::config::{self, Config};
 use common::key::Key;
 use common::messages::Feed;
 
-const KAFKA_URL: &str = "127.0.0.1:9092";
-const ADDING_URL: &str = "127.0.0.1:3042";
-
 struct UrlCodec;
 
 impl UdpCodec for UrlCodec {
@@ -52,50 +48,59 @@ impl UdpCodec for UrlCodec {
     }
 }
 
-fn recv_known_keys() -> HashSet<Key> {
-    let mut consumer = Consumer::from_hosts(vec![KAFKA_URL.to_owned()])
+fn recv_known_keys(config: &Config) -> HashSet<Key> {
+    let mut consumer = Consumer::from_hosts(config.kafka.hosts())
         .with_fallback_offset(FetchOffset::Earliest)
-        .with_topic("feeds".to_owned())
+        .with_topic(config.topics.feeds.clone())
         .create().unwrap();
 
     let message_sets = consumer.poll().unwrap();
 
-    message_sets.iter()
-        .flat_map(|ms| ms.messages())
-        .filter_map(|message| String::from_utf8(message.key.to_vec()).ok())
-        .map(Key::from)
-        .collect()
+    let mut keys = HashSet::new();
+
+    for message in message_sets.iter().flat_map(|ms| ms.messages()) {
+        let key = match String::from_utf8(message.key.to_vec()) {
+            Ok(key) => Key::from(key),
+            Err(_) => continue
+        };
+
+        // Empty values are tombstones of moved or removed feeds.
+        if message.value.is_empty() {
+            keys.remove(&key);
+        } else {
+            keys.insert(key);
+        }
+    }
+
+    keys
 }
 
-fn send_feed<P: Partitioner>(producer: &mut Producer<P>, key: Key, url: Url) {
-    let feed = Feed {
-        url,
-        source: Url::parse("http://unknown").unwrap(),
-        interval: 0,
-        augmented: Timespec::new(0, 0)
-    };
+fn send_feed<P: Partitioner>(producer: &mut Producer<P>, topic: &str, key: Key, url: Url) {
+    let feed = Feed::new(Url::parse("http://unknown").unwrap(), url);
 
     let key: String = key.into();
     let value = serde_json::to_vec(&feed).unwrap();
 
-    producer.send(&Record::from_key_value("feeds", key, value)).unwrap();
+    producer.send(&Record::from_key_value(topic, key, value)).unwrap();
 }
 
 fn main() {
     logger::init().unwrap();
 
-    let mut producer = Producer::from_hosts(vec![KAFKA_URL.to_owned()]).create().unwrap();
+    let config = config::load().unwrap();
+
+    let mut producer = Producer::from_hosts(config.kafka.hosts()).create().unwrap();
 
     info!("Receiving known keys...");
 
-    let mut known_keys = recv_known_keys();
+    let mut known_keys = recv_known_keys(&config);
 
-    info!("Start adding new feeds from {}...", ADDING_URL);
+    info!("Start adding new feeds from {}...", config.scout.adding_url);
 
     let mut lp = Core::new().unwrap();
     let handle = lp.handle();
 
-    let addr = &ADDING_URL.parse().unwrap();
+    let addr = &config.scout.adding_url.parse().unwrap();
 
     let adding = UdpSocket::bind(addr, &handle).unwrap().framed(UrlCodec)
         .map(|url| {
@@ -110,7 +115,7 @@ fn main() {
             info!("Added {} ({})", key, url);
 
             known_keys.insert(key.clone());
-            send_feed(&mut producer, key, url);
+            send_feed(&mut producer, &config.topics.feeds, key, url);
 
             ()
         })
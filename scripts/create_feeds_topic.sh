@@ -10,4 +10,4 @@ kafka-topics.sh \
     --replication-factor 1 \
     --config cleanup.policy=compact \
     --config compression.type=uncompressed \
-    --config max.message.bytes=$((4 * 1024))
+    --config max.message.bytes=$((64 * 1024))